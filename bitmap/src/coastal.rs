@@ -0,0 +1,36 @@
+use parse::{Def, DefinitionFile, Kind};
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::graph::ProvinceGraph;
+
+/// Works out what the `coastal` flag of each land province in `graph` should be: true when
+/// it touches a sea province. Provinces that are not land, or have no pixels, are left out.
+pub fn coastal_flags(defs: &[Def], graph: &ProvinceGraph) -> BTreeMap<usize, bool> {
+  let kinds: HashMap<usize, Kind> = defs.iter().map(|def| (def.id, def.kind)).collect();
+  defs.iter()
+    .filter(|def| def.kind == Kind::Land && graph.contains(def.id))
+    .map(|def| {
+      let coastal = graph.neighbours(def.id)
+        .any(|(id, _)| kinds.get(&id) == Some(&Kind::Sea));
+      (def.id, coastal)
+    })
+    .collect()
+}
+
+/// Sets the `coastal` flag of every land province in `defs` from `graph`, returning the ids
+/// of the provinces whose flag was flipped.
+pub fn recompute_coastal(defs: &mut DefinitionFile, graph: &ProvinceGraph) -> Vec<usize> {
+  let flags = coastal_flags(&defs.to_defs(), graph);
+  let mut flipped = Vec::new();
+  for def in defs.defs_mut() {
+    if let Some(&coastal) = flags.get(&def.id) {
+      if def.coastal != coastal {
+        def.coastal = coastal;
+        flipped.push(def.id);
+      };
+    };
+  };
+
+  flipped
+}
@@ -0,0 +1,172 @@
+use image::{RgbImage, Rgb};
+use parse::Def;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Which pixels count as touching each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Connectivity {
+  /// Pixels touch if they share an edge.
+  Four,
+  /// Pixels touch if they share an edge or a corner.
+  Eight
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphOptions {
+  pub connectivity: Connectivity,
+  /// Whether the left and right edges of the map touch, as they do in game.
+  pub wrap_x: bool
+}
+
+impl Default for GraphOptions {
+  fn default() -> GraphOptions {
+    GraphOptions {
+      connectivity: Connectivity::Four,
+      wrap_x: true
+    }
+  }
+}
+
+/// Which colors in a province bitmap touch each other, and along how many pixel contacts.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ColorGraph {
+  edges: HashMap<[u8; 3], HashMap<[u8; 3], u32>>
+}
+
+impl ColorGraph {
+  pub fn from_image(img: &RgbImage, options: GraphOptions) -> ColorGraph {
+    let mut graph = ColorGraph::default();
+    let (width, height) = img.dimensions();
+    let eight = options.connectivity == Connectivity::Eight;
+    for y in 0..height {
+      for x in 0..width {
+        let &Rgb(color) = img.get_pixel(x, y);
+        graph.edges.entry(color).or_default();
+
+        let right = step_x(x, 1, width, options.wrap_x);
+        let left = step_x(x, -1, width, options.wrap_x);
+        let below = if y + 1 < height { Some(y + 1) } else { None };
+
+        let mut neighbours = [None; 4];
+        neighbours[0] = right.map(|x| (x, y));
+        neighbours[1] = below.map(|y| (x, y));
+        if eight {
+          neighbours[2] = right.and_then(|x| below.map(|y| (x, y)));
+          neighbours[3] = left.and_then(|x| below.map(|y| (x, y)));
+        };
+
+        for (nx, ny) in neighbours.iter().flatten().cloned() {
+          let &Rgb(other) = img.get_pixel(nx, ny);
+          if other != color {
+            graph.add_contact(color, other);
+          };
+        };
+      };
+    };
+
+    graph
+  }
+
+  fn add_contact(&mut self, a: [u8; 3], b: [u8; 3]) {
+    *self.edges.entry(a).or_default().entry(b).or_insert(0) += 1;
+    *self.edges.entry(b).or_default().entry(a).or_insert(0) += 1;
+  }
+
+  /// Every color present in the image.
+  pub fn colors(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+    self.edges.keys().cloned()
+  }
+
+  pub fn contains(&self, color: [u8; 3]) -> bool {
+    self.edges.contains_key(&color)
+  }
+
+  /// The colors touching `color`, along with the length of the border shared with each.
+  pub fn neighbours(&self, color: [u8; 3]) -> impl Iterator<Item = ([u8; 3], u32)> + '_ {
+    self.edges.get(&color).into_iter().flatten().map(|(&color, &border)| (color, border))
+  }
+
+  /// The number of pixel contacts between two colors, or 0 if they do not touch.
+  pub fn border(&self, a: [u8; 3], b: [u8; 3]) -> u32 {
+    self.edges.get(&a).and_then(|edges| edges.get(&b)).cloned().unwrap_or(0)
+  }
+
+  /// Maps this graph onto province ids using the colors in `defs`.
+  pub fn to_provinces(&self, defs: &[Def]) -> ProvinceGraph {
+    let ids: HashMap<[u8; 3], usize> = defs.iter().map(|def| (def.rgb, def.id)).collect();
+    let mut graph = ProvinceGraph::default();
+    for (color, edges) in &self.edges {
+      let id = match ids.get(color) {
+        Some(&id) => id,
+        None => { graph.unmapped.insert(*color); continue }
+      };
+
+      let node = graph.edges.entry(id).or_default();
+      for (other, &border) in edges {
+        if let Some(&other) = ids.get(other) {
+          *node.entry(other).or_insert(0) += border;
+        };
+      };
+    };
+
+    graph
+  }
+}
+
+/// Which provinces touch each other, and along how many pixel contacts.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProvinceGraph {
+  edges: BTreeMap<usize, BTreeMap<usize, u32>>,
+  /// Colors in the image that no definition uses.
+  pub unmapped: BTreeSet<[u8; 3]>
+}
+
+impl ProvinceGraph {
+  pub fn build(img: &RgbImage, defs: &[Def], options: GraphOptions) -> ProvinceGraph {
+    ColorGraph::from_image(img, options).to_provinces(defs)
+  }
+
+  /// Every province that has pixels in the image.
+  pub fn provinces(&self) -> impl Iterator<Item = usize> + '_ {
+    self.edges.keys().cloned()
+  }
+
+  pub fn contains(&self, id: usize) -> bool {
+    self.edges.contains_key(&id)
+  }
+
+  /// The provinces touching `id`, along with the length of the border shared with each.
+  pub fn neighbours(&self, id: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+    self.edges.get(&id).into_iter().flatten().map(|(&id, &border)| (id, border))
+  }
+
+  /// The number of pixel contacts between two provinces, or 0 if they do not touch.
+  pub fn border(&self, a: usize, b: usize) -> u32 {
+    self.edges.get(&a).and_then(|edges| edges.get(&b)).cloned().unwrap_or(0)
+  }
+
+  /// Every pair of touching provinces, smallest id first, with their shared border length.
+  pub fn edges(&self) -> impl Iterator<Item = (usize, usize, u32)> + '_ {
+    self.edges.iter().flat_map(|(&a, edges)| {
+      edges.iter().filter(move |(&b, _)| a < b).map(move |(&b, &border)| (a, b, border))
+    })
+  }
+
+  /// Provinces with pixels in the image that touch no other province.
+  pub fn isolated(&self) -> impl Iterator<Item = usize> + '_ {
+    self.edges.iter().filter(|(_, edges)| edges.is_empty()).map(|(&id, _)| id)
+  }
+}
+
+#[inline]
+pub(crate) fn step_x(x: u32, dx: i32, width: u32, wrap: bool) -> Option<u32> {
+  let nx = x as i64 + dx as i64;
+  if nx >= 0 && nx < width as i64 {
+    Some(nx as u32)
+  } else if wrap && width > 1 {
+    Some(nx.rem_euclid(width as i64) as u32)
+  } else {
+    None
+  }
+}
@@ -0,0 +1,212 @@
+use image::{GrayImage, ImageError, Luma, RgbImage};
+use image::io::Reader;
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::fmt;
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+/// 72 DPI, which is what most image editors write.
+const PIXELS_PER_METER: i32 = 2835;
+
+/// Reads an image of any format the game uses, whatever its extension says, and converts it to
+/// 24-bit RGB. This covers 24-bit and 32-bit BMPs, 8-bit indexed BMPs such as `terrain.bmp` and
+/// `rivers.bmp`, and PNGs. Any alpha channel is dropped.
+pub fn read_image<P: AsRef<Path>>(path: P) -> Result<RgbImage, BitmapError> {
+  let path = path.as_ref();
+  let file = File::open(path).map_err(|err| BitmapError::Io(path.to_owned(), err))?;
+  let reader = Reader::new(BufReader::new(file))
+    .with_guessed_format()
+    .map_err(|err| BitmapError::Io(path.to_owned(), err))?;
+  let img = reader.decode().map_err(|err| BitmapError::Image(path.to_owned(), err))?;
+  Ok(img.into_rgb8())
+}
+
+/// Reads an 8-bit indexed BMP such as `terrain.bmp` as the palette index of each pixel, which
+/// is what the game matches against, rather than the color its palette gives it.
+pub fn read_indexed<P: AsRef<Path>>(path: P) -> Result<GrayImage, BitmapError> {
+  let path = path.as_ref();
+  let data = fs::read(path).map_err(|err| BitmapError::Io(path.to_owned(), err))?;
+  decode_indexed(&data).ok_or_else(|| BitmapError::NotIndexed(path.to_owned()))
+}
+
+/// Decodes an uncompressed 8-bit BMP, stored either bottom-up or top-down.
+fn decode_indexed(data: &[u8]) -> Option<GrayImage> {
+  let u16_at = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?));
+  let u32_at = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+  if data.get(..2)? != b"BM" || u16_at(28)? != 8 || u32_at(30)? != 0 { return None };
+  let offset = u32_at(10)? as usize;
+  let width = u32_at(18)? as i32;
+  let height = u32_at(22)? as i32;
+  if width <= 0 || height == 0 { return None };
+
+  let (width, rows) = (width as u32, height.unsigned_abs());
+  let row_size = width.div_ceil(4) as usize * 4;
+  let mut img = GrayImage::new(width, rows);
+  for row in 0..rows {
+    let start = offset + row as usize * row_size;
+    let indices = data.get(start..start + width as usize)?;
+    let y = if height > 0 { rows - 1 - row } else { row };
+    for (x, &index) in indices.iter().enumerate() {
+      img.put_pixel(x as u32, y, Luma([index]));
+    };
+  };
+
+  Some(img)
+}
+
+/// Reads the first of `paths` that exists. A file that exists but cannot be read is an error
+/// rather than being skipped over.
+pub fn read_first<P: AsRef<Path>>(paths: &[P]) -> Result<(PathBuf, RgbImage), BitmapError> {
+  for path in paths.iter().map(AsRef::as_ref) {
+    match read_image(path) {
+      Err(BitmapError::Io(_, err)) if err.kind() == io::ErrorKind::NotFound => continue,
+      result => return result.map(|img| (path.to_owned(), img))
+    };
+  };
+
+  Err(BitmapError::NotFound(paths.iter().map(|path| path.as_ref().to_owned()).collect()))
+}
+
+/// Writes `img` as an uncompressed 24-bit bottom-up BMP with no alpha, the only format the game
+/// accepts for `provinces.bmp`.
+pub fn write_bmp<P: AsRef<Path>>(path: P, img: &RgbImage) -> Result<(), BitmapError> {
+  let path = path.as_ref();
+  File::create(path)
+    .and_then(|file| encode_bmp(img, BufWriter::new(file)))
+    .map_err(|err| BitmapError::Io(path.to_owned(), err))
+}
+
+/// Encodes `img` the same way as [`write_bmp`].
+pub fn encode_bmp<W: Write>(img: &RgbImage, mut out: W) -> io::Result<()> {
+  let (width, height) = img.dimensions();
+  let row_size = (width * 3).div_ceil(4) * 4;
+  let data_size = row_size * height;
+  let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+
+  out.write_all(b"BM")?;
+  out.write_all(&(offset + data_size).to_le_bytes())?;
+  out.write_all(&[0; 4])?;
+  out.write_all(&offset.to_le_bytes())?;
+
+  out.write_all(&INFO_HEADER_SIZE.to_le_bytes())?;
+  out.write_all(&(width as i32).to_le_bytes())?;
+  out.write_all(&(height as i32).to_le_bytes())?;
+  out.write_all(&1u16.to_le_bytes())?;
+  out.write_all(&24u16.to_le_bytes())?;
+  out.write_all(&0u32.to_le_bytes())?;
+  out.write_all(&data_size.to_le_bytes())?;
+  out.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+  out.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+  out.write_all(&0u32.to_le_bytes())?;
+  out.write_all(&0u32.to_le_bytes())?;
+
+  let padding = (row_size - width * 3) as usize;
+  let mut row = Vec::with_capacity(row_size as usize);
+  for y in (0..height).rev() {
+    row.clear();
+    for x in 0..width {
+      let [r, g, b] = img.get_pixel(x, y).0;
+      row.extend_from_slice(&[b, g, r]);
+    };
+
+    row.resize(row.len() + padding, 0);
+    out.write_all(&row)?;
+  };
+
+  out.flush()
+}
+
+#[derive(Debug)]
+pub enum BitmapError {
+  Io(PathBuf, io::Error),
+  Image(PathBuf, ImageError),
+  /// None of the given paths exist.
+  NotFound(Vec<PathBuf>),
+  /// The file is not an uncompressed 8-bit indexed BMP.
+  NotIndexed(PathBuf)
+}
+
+impl fmt::Display for BitmapError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      BitmapError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+      BitmapError::Image(path, err) => write!(f, "{}: {}", path.display(), err),
+      BitmapError::NotFound(paths) => {
+        let paths = paths.iter()
+          .map(|path| path.display().to_string())
+          .collect::<Vec<_>>();
+        write!(f, "could not find {}", paths.join(" or "))
+      },
+      BitmapError::NotIndexed(path) => write!(f, "{}: not an uncompressed 8-bit indexed bmp", path.display())
+    }
+  }
+}
+
+impl Error for BitmapError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// An uncompressed 8-bit BMP with an empty palette and the given rows, top row first.
+  fn indexed_bmp(rows: &[&[u8]], bottom_up: bool) -> Vec<u8> {
+    let (width, height) = (rows[0].len() as u32, rows.len() as i32);
+    let row_size = width.div_ceil(4) * 4;
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + 1024;
+    let mut data = b"BM".to_vec();
+    data.extend((offset + row_size * height as u32).to_le_bytes());
+    data.extend([0; 4]);
+    data.extend(offset.to_le_bytes());
+    data.extend(INFO_HEADER_SIZE.to_le_bytes());
+    data.extend(width.to_le_bytes());
+    data.extend((if bottom_up { height } else { -height }).to_le_bytes());
+    data.extend(1u16.to_le_bytes());
+    data.extend(8u16.to_le_bytes());
+    data.extend([0; 24]);
+    data.extend([0; 1024]);
+    let mut ordered = rows.to_vec();
+    if bottom_up { ordered.reverse() };
+    for row in ordered {
+      data.extend(row);
+      data.resize(data.len() + (row_size - width) as usize, 0);
+    };
+
+    data
+  }
+
+  #[test]
+  fn encode_round_trip() {
+    // A width of 3 leaves each row 9 bytes long, so every row is padded
+    let img = RgbImage::from_fn(3, 2, |x, y| image::Rgb([x as u8 * 80, y as u8 * 200, 7]));
+    let mut data = Vec::new();
+    encode_bmp(&img, &mut data).unwrap();
+    assert_eq!(&data[..2], b"BM");
+    assert_eq!(data.len(), (FILE_HEADER_SIZE + INFO_HEADER_SIZE) as usize + 12 * 2);
+    assert_eq!(u32::from_le_bytes(data[2..6].try_into().unwrap()) as usize, data.len());
+
+    let decoded = image::load_from_memory(&data).unwrap().into_rgb8();
+    assert_eq!(decoded, img);
+  }
+
+  #[test]
+  fn indexed_rows_are_read_in_order() {
+    for &bottom_up in &[true, false] {
+      let img = decode_indexed(&indexed_bmp(&[&[1, 2, 3], &[4, 5, 6]], bottom_up)).unwrap();
+      assert_eq!(img.dimensions(), (3, 2));
+      assert_eq!(img.into_raw(), [1, 2, 3, 4, 5, 6]);
+    };
+  }
+
+  #[test]
+  fn only_8_bit_is_indexed() {
+    let mut data = indexed_bmp(&[&[1]], true);
+    data[28] = 24;
+    assert!(decode_indexed(&data).is_none());
+    assert!(decode_indexed(b"BM").is_none());
+  }
+}
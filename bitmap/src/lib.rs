@@ -0,0 +1,16 @@
+extern crate image;
+extern crate parse;
+
+mod coastal;
+mod graph;
+mod io;
+mod rows;
+mod stats;
+mod validate;
+
+pub use crate::coastal::*;
+pub use crate::graph::*;
+pub use crate::io::*;
+pub use crate::rows::*;
+pub use crate::stats::*;
+pub use crate::validate::*;
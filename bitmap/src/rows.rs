@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{available_parallelism, scope};
+
+/// Splits `data`, laid out as rows of `row_len` items each, into bands of whole rows and runs
+/// `f` over every band on its own thread. `f` is given the index of the first row in its band.
+/// Returns what `f` returned for each band, from the top band down.
+pub fn par_rows<T, R, F>(data: &mut [T], row_len: usize, f: F) -> Vec<R>
+where T: Send, R: Send, F: Fn(usize, &mut [T]) -> R + Sync {
+  if data.is_empty() { return Vec::new() };
+  let rows = rows_per_thread(data.len() / row_len);
+  scope(|scope| {
+    let handles = data.chunks_mut(rows * row_len).enumerate()
+      .map(|(i, band)| {
+        let f = &f;
+        scope.spawn(move || f(i * rows, band))
+      })
+      .collect::<Vec<_>>();
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+  })
+}
+
+/// How many rows each thread should handle.
+fn rows_per_thread(height: usize) -> usize {
+  let threads = available_parallelism().map_or(1, |n| n.get());
+  height.div_ceil(threads).max(1)
+}
+
+/// Counts steps of a task done across threads, printing how far along it is to stderr every
+/// time it passes another tenth of the way. A task with no steps prints nothing.
+#[derive(Debug)]
+pub struct Progress {
+  task: &'static str,
+  done: AtomicUsize,
+  total: usize
+}
+
+impl Progress {
+  pub fn new(task: &'static str, total: usize) -> Progress {
+    Progress { task, done: AtomicUsize::new(0), total }
+  }
+
+  pub fn advance(&self) {
+    let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+    if self.total == 0 { return };
+    if done * 10 / self.total != (done - 1) * 10 / self.total {
+      eprintln!("{}... {}%", self.task, done * 100 / self.total);
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bands_cover_every_row_once() {
+    for &height in &[1, 2, 7, 64, 101] {
+      let mut data = vec![0usize; height * 3];
+      let bands = par_rows(&mut data, 3, |first_row, band| {
+        for (row, items) in band.chunks_mut(3).enumerate() {
+          items.iter_mut().for_each(|item| *item = first_row + row);
+        };
+
+        (first_row, band.len() / 3)
+      });
+
+      let mut next_row = 0;
+      for (first_row, rows) in bands {
+        assert_eq!(first_row, next_row);
+        assert!(rows > 0);
+        next_row += rows;
+      };
+
+      assert_eq!(next_row, height);
+      assert!(data.chunks(3).enumerate().all(|(row, items)| items.iter().all(|&item| item == row)));
+    };
+  }
+
+  #[test]
+  fn no_rows() {
+    let bands = par_rows(&mut [0u8; 0], 4, |first_row, _| first_row);
+    assert!(bands.is_empty());
+  }
+
+  #[test]
+  fn progress_with_no_steps() {
+    let progress = Progress::new("nothing", 0);
+    progress.advance();
+    progress.advance();
+  }
+}
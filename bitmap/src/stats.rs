@@ -0,0 +1,139 @@
+use image::{RgbImage, Rgb};
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::graph::{Connectivity, GraphOptions, step_x};
+
+/// The smallest rectangle containing every pixel of a color, inclusive on both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bounds {
+  pub min: [u32; 2],
+  pub max: [u32; 2]
+}
+
+impl Bounds {
+  #[inline]
+  fn new(x: u32, y: u32) -> Bounds {
+    Bounds { min: [x, y], max: [x, y] }
+  }
+
+  #[inline]
+  fn extend(&mut self, x: u32, y: u32) {
+    self.min = [self.min[0].min(x), self.min[1].min(y)];
+    self.max = [self.max[0].max(x), self.max[1].max(y)];
+  }
+
+  pub fn width(&self) -> u32 {
+    self.max[0] - self.min[0] + 1
+  }
+
+  pub fn height(&self) -> u32 {
+    self.max[1] - self.min[1] + 1
+  }
+}
+
+/// A connected run of pixels of one color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+  pub pixels: u64,
+  /// The first pixel of this piece in reading order.
+  pub start: [u32; 2]
+}
+
+/// Pixel statistics for a single color in a province bitmap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorStats {
+  pub pixels: u64,
+  pub bounds: Bounds,
+  /// The x and y coordinates of every pixel of this color, summed.
+  pub sum: [u64; 2],
+  /// Every connected piece of this color, largest first.
+  pub pieces: Vec<Piece>
+}
+
+impl ColorStats {
+  /// The first pixel of this color in reading order.
+  pub fn start(&self) -> [u32; 2] {
+    self.pieces.iter().map(|piece| piece.start).min_by_key(|&[x, y]| (y, x)).unwrap_or(self.bounds.min)
+  }
+
+  /// The mean position of every pixel of this color. This does not account for wrapping,
+  /// and may fall outside of the color if it is not convex.
+  pub fn centroid(&self) -> [f64; 2] {
+    let pixels = self.pixels.max(1) as f64;
+    [self.sum[0] as f64 / pixels, self.sum[1] as f64 / pixels]
+  }
+
+  pub fn is_connected(&self) -> bool {
+    self.pieces.len() <= 1
+  }
+}
+
+/// Collects pixel counts, bounds, centroids and connected pieces for every color in `img`.
+pub fn color_stats(img: &RgbImage, options: GraphOptions) -> BTreeMap<[u8; 3], ColorStats> {
+  let (width, height) = img.dimensions();
+  let mut visited = vec![false; width as usize * height as usize];
+  let mut stats: BTreeMap<[u8; 3], ColorStats> = BTreeMap::new();
+  let mut stack = Vec::new();
+  for y in 0..height {
+    for x in 0..width {
+      if visited[(y * width + x) as usize] { continue };
+      let &Rgb(color) = img.get_pixel(x, y);
+      let entry = stats.entry(color).or_insert_with(|| ColorStats {
+        pixels: 0,
+        bounds: Bounds::new(x, y),
+        sum: [0, 0],
+        pieces: Vec::new()
+      });
+
+      let mut piece = Piece { pixels: 0, start: [x, y] };
+      visited[(y * width + x) as usize] = true;
+      stack.push((x, y));
+      while let Some((px, py)) = stack.pop() {
+        piece.pixels += 1;
+        entry.bounds.extend(px, py);
+        entry.sum[0] += px as u64;
+        entry.sum[1] += py as u64;
+        for (nx, ny) in neighbours(px, py, width, height, options) {
+          let index = (ny * width + nx) as usize;
+          if !visited[index] && img.get_pixel(nx, ny).0 == color {
+            visited[index] = true;
+            stack.push((nx, ny));
+          };
+        };
+      };
+
+      entry.pixels += piece.pixels;
+      entry.pieces.push(piece);
+    };
+  };
+
+  for entry in stats.values_mut() {
+    entry.pieces.sort_by_key(|piece| Reverse(piece.pixels));
+  };
+
+  stats
+}
+
+/// Every pixel touching (`x`, `y`) under `options`.
+pub(crate) fn neighbours(x: u32, y: u32, width: u32, height: u32, options: GraphOptions) -> impl Iterator<Item = (u32, u32)> {
+  let xs = [step_x(x, -1, width, options.wrap_x), Some(x), step_x(x, 1, width, options.wrap_x)];
+  let ys = [y.checked_sub(1), Some(y), if y + 1 < height { Some(y + 1) } else { None }];
+  let eight = options.connectivity == Connectivity::Eight;
+  let mut out = Vec::with_capacity(8);
+  for (i, nx) in xs.iter().enumerate() {
+    for (j, ny) in ys.iter().enumerate() {
+      let (nx, ny) = match (nx, ny) {
+        (Some(nx), Some(ny)) => (*nx, *ny),
+        _ => continue
+      };
+
+      let diagonal = i != 1 && j != 1;
+      if (i == 1 && j == 1) || (diagonal && !eight) || (nx == x && ny == y) { continue };
+      out.push((nx, ny));
+    };
+  };
+
+  out.into_iter()
+}
@@ -0,0 +1,174 @@
+use image::RgbImage;
+use parse::{Def, Diagnostic, DiagnosticKind, Kind};
+
+use std::collections::HashMap;
+
+use crate::graph::{GraphOptions, step_x};
+use crate::stats::color_stats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValidateOptions {
+  /// Provinces with fewer pixels than this are reported as too small.
+  pub min_pixels: u64,
+  /// Provinces with more pixels than this are reported as too large.
+  pub max_pixels: Option<u64>,
+  pub graph: GraphOptions
+}
+
+impl Default for ValidateOptions {
+  fn default() -> ValidateOptions {
+    ValidateOptions {
+      min_pixels: 8,
+      max_pixels: None,
+      graph: GraphOptions::default()
+    }
+  }
+}
+
+/// Runs the province checks the game writes to `error.log` over a province bitmap and
+/// its definitions.
+pub fn validate_map(img: &RgbImage, defs: &[Def], options: ValidateOptions) -> Vec<Diagnostic> {
+  let mut out = Vec::new();
+  let stats = color_stats(img, options.graph);
+  let ids: HashMap<[u8; 3], usize> = defs.iter().map(|def| (def.rgb, def.id)).collect();
+
+  for (&color, stats) in &stats {
+    let [r, g, b] = color;
+    let id = match ids.get(&color) {
+      Some(&id) => id,
+      None => {
+        let message = format!("color [{},{},{}] has no definition in definition.csv", r, g, b);
+        out.push(Diagnostic::new(DiagnosticKind::UndefinedColor, message)
+          .with_colors(Some(color)).at(stats.start()));
+        continue;
+      }
+    };
+
+    if stats.pixels < options.min_pixels {
+      let message = format!("province {} is too small ({} pixels)", id, stats.pixels);
+      out.push(Diagnostic::new(DiagnosticKind::TooSmall, message)
+        .with_ids(Some(id)).with_colors(Some(color)).at(stats.start()));
+    };
+
+    if matches!(options.max_pixels, Some(max) if stats.pixels > max) {
+      let message = format!("province {} is too large ({} pixels)", id, stats.pixels);
+      out.push(Diagnostic::new(DiagnosticKind::TooLarge, message)
+        .with_ids(Some(id)).with_colors(Some(color)).at(stats.start()));
+    };
+
+    for piece in stats.pieces.iter().skip(1) {
+      let message = format!("province {} has a disconnected piece of {} pixels", id, piece.pixels);
+      out.push(Diagnostic::new(DiagnosticKind::Disconnected, message)
+        .with_ids(Some(id)).with_colors(Some(color)).at(piece.start));
+    };
+  };
+
+  for def in defs {
+    if def.is_initial() { continue };
+    if !stats.contains_key(&def.rgb) {
+      let message = format!("province {} has no pixels in provinces.bmp", def.id);
+      out.push(Diagnostic::new(DiagnosticKind::NoPixels, message)
+        .with_ids(Some(def.id)).with_colors(Some(def.rgb)));
+    };
+
+    if def.kind == Kind::Lake && def.coastal {
+      let message = format!("province {} is a lake but is marked coastal", def.id);
+      out.push(Diagnostic::new(DiagnosticKind::CoastalLake, message).with_ids(Some(def.id)));
+    };
+
+    if def.kind == Kind::Land && def.continent == 0 {
+      let message = format!("province {} is land but has no continent", def.id);
+      out.push(Diagnostic::new(DiagnosticKind::NoContinent, message).with_ids(Some(def.id)));
+    };
+  };
+
+  out.extend(x_crossings(img, &ids, options.graph.wrap_x));
+  out
+}
+
+/// Finds every 2x2 block where provinces meet corner to corner, which the game rejects: either
+/// four different colors, or two colors on opposite corners as in `a b / b a`.
+fn x_crossings(img: &RgbImage, ids: &HashMap<[u8; 3], usize>, wrap_x: bool) -> Vec<Diagnostic> {
+  let (width, height) = img.dimensions();
+  let mut out = Vec::new();
+  for y in 0..height.saturating_sub(1) {
+    for x in 0..width {
+      let right = match step_x(x, 1, width, wrap_x) {
+        Some(right) => right,
+        None => continue
+      };
+
+      let corners = [[x, y], [right, y], [x, y + 1], [right, y + 1]].map(|[x, y]| img.get_pixel(x, y).0);
+      let mut colors = Vec::with_capacity(4);
+      for color in corners {
+        if !colors.contains(&color) {
+          colors.push(color);
+        };
+      };
+
+      let crossing = match colors.len() {
+        4 => true,
+        2 => corners[0] == corners[3] && corners[1] == corners[2],
+        _ => false
+      };
+
+      if crossing {
+        let names = colors.iter()
+          .map(|&color| match ids.get(&color) {
+            Some(id) => format!("province {}", id),
+            None => format!("color [{},{},{}]", color[0], color[1], color[2])
+          })
+          .collect::<Vec<_>>();
+        let message = format!("map invalid X crossing between {}", names.join(", "));
+        out.push(Diagnostic::new(DiagnosticKind::XCrossing, message)
+          .with_ids(colors.iter().filter_map(|color| ids.get(color).copied()))
+          .with_colors(colors).at([x, y]));
+      };
+    };
+  };
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const A: [u8; 3] = [10, 0, 0];
+  const B: [u8; 3] = [20, 0, 0];
+  const C: [u8; 3] = [30, 0, 0];
+  const D: [u8; 3] = [40, 0, 0];
+
+  fn image(rows: &[&[[u8; 3]]]) -> RgbImage {
+    RgbImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| image::Rgb(rows[y as usize][x as usize]))
+  }
+
+  fn crossings(img: &RgbImage, wrap_x: bool) -> Vec<Option<[u32; 2]>> {
+    let ids = [(A, 1), (B, 2), (C, 3)].iter().copied().collect();
+    x_crossings(img, &ids, wrap_x).into_iter().map(|diagnostic| diagnostic.position).collect()
+  }
+
+  #[test]
+  fn four_provinces_meeting() {
+    let img = image(&[&[A, B], &[C, D]]);
+    let diagnostics = x_crossings(&img, &[(A, 1), (B, 2), (C, 3)].iter().copied().collect(), false);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::XCrossing);
+    assert_eq!(diagnostics[0].ids, [1, 2, 3]);
+    assert_eq!(diagnostics[0].colors, [A, B, C, D]);
+  }
+
+  #[test]
+  fn opposite_corners() {
+    assert_eq!(crossings(&image(&[&[A, B], &[B, A]]), false), [Some([0, 0])]);
+    assert!(crossings(&image(&[&[A, B], &[A, B]]), false).is_empty());
+    assert!(crossings(&image(&[&[A, B], &[C, C]]), false).is_empty());
+  }
+
+  #[test]
+  fn crossings_wrap_around() {
+    let img = image(&[&[A, C, B], &[B, C, A]]);
+    assert!(crossings(&img, false).is_empty());
+    assert_eq!(crossings(&img, true), [Some([2, 0])]);
+  }
+}
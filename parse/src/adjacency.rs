@@ -0,0 +1,226 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::definition::*;
+
+pub const ADJACENCIES_HEADER: &str = "From;To;Type;Through;start_x;start_y;stop_x;stop_y;adjacency_rule_name;Comment";
+pub const ADJACENCIES_END: &str = "-1;-1;;-1;-1;-1;-1;-1;-1";
+
+/// The `Type` column of an adjacency. The game only knows land (left empty), `sea` and
+/// `impassable`; anything else is kept as it was written so that it is not lost.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AdjacencyKind {
+  Land,
+  Sea,
+  Impassable,
+  Other(String)
+}
+
+impl AdjacencyKind {
+  #[inline]
+  pub fn as_str(&self) -> &str {
+    match self {
+      AdjacencyKind::Land => "",
+      AdjacencyKind::Sea => "sea",
+      AdjacencyKind::Impassable => "impassable",
+      AdjacencyKind::Other(kind) => kind
+    }
+  }
+}
+
+impl From<&str> for AdjacencyKind {
+  fn from(s: &str) -> AdjacencyKind {
+    match s {
+      "" => AdjacencyKind::Land,
+      "sea" => AdjacencyKind::Sea,
+      "impassable" => AdjacencyKind::Impassable,
+      _ => AdjacencyKind::Other(s.to_owned())
+    }
+  }
+}
+
+impl fmt::Display for AdjacencyKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// A row of `map/adjacencies.csv`. Optional columns written as `-1` in the file are `None` here.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Adjacency {
+  pub from: usize,
+  pub to: usize,
+  pub kind: AdjacencyKind,
+  pub through: Option<usize>,
+  pub start: Option<[i32; 2]>,
+  pub stop: Option<[i32; 2]>,
+  pub rule: String,
+  pub comment: String
+}
+
+impl Adjacency {
+  pub fn new(from: usize, to: usize, kind: AdjacencyKind) -> Adjacency {
+    Adjacency {
+      from,
+      to,
+      kind,
+      through: None,
+      start: None,
+      stop: None,
+      rule: String::new(),
+      comment: String::new()
+    }
+  }
+
+  /// Returns every province this adjacency refers to.
+  pub fn provinces(&self) -> impl Iterator<Item = usize> {
+    vec![Some(self.from), Some(self.to), self.through].into_iter().flatten()
+  }
+}
+
+impl fmt::Display for Adjacency {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let [start_x, start_y] = self.start.unwrap_or([-1, -1]);
+    let [stop_x, stop_y] = self.stop.unwrap_or([-1, -1]);
+    writeln!(
+      f,
+      "{};{};{};{};{};{};{};{};{};{}",
+      self.from,
+      self.to,
+      self.kind,
+      self.through.map_or(-1, |through| through as i64),
+      start_x,
+      start_y,
+      stop_x,
+      stop_y,
+      self.rule,
+      self.comment
+    )
+  }
+}
+
+/// Parses the contents of `map/adjacencies.csv`, skipping the header and stopping at the
+/// `-1;-1;...` line that ends the file.
+pub fn parse_adjacencies(content: impl AsRef<str>) -> Result<Vec<Adjacency>, CsvError> {
+  let content = content.as_ref();
+  let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+  let mut out = Vec::new();
+  for (i, line) in content.split('\n').enumerate() {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let first = line.split(';').next().unwrap_or("").trim();
+    if line.trim().is_empty() || first.eq_ignore_ascii_case("from") { continue };
+    if first == "-1" { break };
+    out.push(parse_adjacency_line(line, i + 1)?);
+  };
+
+  Ok(out)
+}
+
+fn parse_adjacency_line(line: &str, line_number: usize) -> Result<Adjacency, CsvError> {
+  let fields = split_fields(line);
+  if fields.len() < 8 {
+    let column = line.chars().count() + 1;
+    let reason = CsvErrorReason::FieldCount { expected: 8, found: fields.len() };
+    return Err(CsvError { line: line_number, column, reason });
+  };
+
+  let invalid = |i: usize| {
+    let (column, text) = fields[i];
+    CsvError { line: line_number, column, reason: CsvErrorReason::InvalidCoordinate(text.trim().to_owned()) }
+  };
+
+  let coordinate = |i: usize| -> Result<Option<i32>, CsvError> {
+    match field::<i64>(&fields, i, line_number, CsvErrorReason::InvalidCoordinate)? {
+      value if value < 0 => Ok(None),
+      value => i32::try_from(value).map(Some).map_err(|_| invalid(i))
+    }
+  };
+
+  let through = match field::<i64>(&fields, 3, line_number, CsvErrorReason::InvalidProvince)? {
+    through if through < 0 => None,
+    through => Some(through as usize)
+  };
+
+  // A point is either given in full or left out in full, so a lone coordinate is an error
+  let point = |x: usize, y: usize| -> Result<Option<[i32; 2]>, CsvError> {
+    match (coordinate(x)?, coordinate(y)?) {
+      (Some(x), Some(y)) => Ok(Some([x, y])),
+      (None, None) => Ok(None),
+      (Some(_), None) => Err(invalid(y)),
+      (None, Some(_)) => Err(invalid(x))
+    }
+  };
+
+  let text = |i: usize| fields.get(i).map_or(String::new(), |&(_, text)| text.to_owned());
+  let comment = fields.get(9..).map_or(String::new(), |rest| {
+    rest.iter().map(|&(_, text)| text).collect::<Vec<_>>().join(";")
+  });
+
+  Ok(Adjacency {
+    from: field(&fields, 0, line_number, CsvErrorReason::InvalidProvince)?,
+    to: field(&fields, 1, line_number, CsvErrorReason::InvalidProvince)?,
+    kind: AdjacencyKind::from(fields[2].1.trim()),
+    through,
+    start: point(4, 5)?,
+    stop: point(6, 7)?,
+    rule: text(8),
+    comment
+  })
+}
+
+/// Writes a complete `map/adjacencies.csv`, including the header and the closing `-1` line.
+pub fn write_adjacencies(adjacencies: &[Adjacency]) -> String {
+  let mut out = format!("{}\n", ADJACENCIES_HEADER);
+  for adjacency in adjacencies {
+    out.push_str(&adjacency.to_string());
+  };
+
+  out.push_str(ADJACENCIES_END);
+  out.push('\n');
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FILE: &str = "From;To;Type;Through;start_x;start_y;stop_x;stop_y;adjacency_rule_name;Comment\n\
+    1;2;sea;3;10;20;30;40;;Strait\n\
+    4;5;impassable;-1;-1;-1;-1;-1;;\n\
+    6;7;;-1;-1;-1;-1;-1;canal_rule;Canal;east\n\
+    8;9;lake;-1;-1;-1;-1;-1;;\n\
+    -1;-1;;-1;-1;-1;-1;-1;-1\n";
+
+  #[test]
+  fn round_trip() {
+    let adjacencies = parse_adjacencies(FILE).unwrap();
+    assert_eq!(adjacencies.len(), 4);
+    assert_eq!(adjacencies[0].through, Some(3));
+    assert_eq!(adjacencies[0].start, Some([10, 20]));
+    assert_eq!(adjacencies[2].kind, AdjacencyKind::Land);
+    assert_eq!(adjacencies[2].comment, "Canal;east");
+    assert_eq!(write_adjacencies(&adjacencies), FILE);
+  }
+
+  #[test]
+  fn unknown_kinds_are_kept() {
+    let adjacencies = parse_adjacencies("1;2;lake;-1;-1;-1;-1;-1;;\n").unwrap();
+    assert_eq!(adjacencies[0].kind, AdjacencyKind::Other("lake".to_owned()));
+    assert_eq!(adjacencies[0].kind.to_string(), "lake");
+  }
+
+  #[test]
+  fn half_filled_point() {
+    let err = parse_adjacencies("1;2;sea;3;10;-1;30;40;;\n").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 14));
+    assert_eq!(err.reason, CsvErrorReason::InvalidCoordinate("-1".to_owned()));
+  }
+
+  #[test]
+  fn provinces() {
+    let mut adjacency = Adjacency::new(1, 2, AdjacencyKind::Sea);
+    assert_eq!(adjacency.provinces().collect::<Vec<_>>(), [1, 2]);
+    adjacency.through = Some(3);
+    assert_eq!(adjacency.provinces().collect::<Vec<_>>(), [1, 2, 3]);
+  }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use crate::definition::Def;
+
+/// Colors that no province may use. Black and white are treated specially by the
+/// scraper and the welder.
+pub const RESERVED_COLORS: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+/// The default minimum distance, as measured by [`color_distance`], between a new color and
+/// the colors of the provinces around it.
+pub const DEFAULT_MIN_DISTANCE: f64 = 48.0;
+
+const COLOR_SPACE: u32 = 1 << 24;
+
+/// Hands out province colors that are not already taken, in an order that only depends on
+/// the seed it was created with.
+///
+/// Candidates are walked with a full-period generator over all 2^24 colors, so every color
+/// is tried exactly once before the allocator gives up.
+#[derive(Debug, Clone)]
+pub struct ColorAllocator {
+  taken: HashSet<[u8; 3]>,
+  state: u32,
+  tried: u32,
+  min_distance: f64
+}
+
+impl ColorAllocator {
+  pub fn new(seed: u64) -> ColorAllocator {
+    ColorAllocator {
+      taken: RESERVED_COLORS.iter().cloned().collect(),
+      state: (seed ^ (seed >> 24) ^ (seed >> 48)) as u32 % COLOR_SPACE,
+      tried: 0,
+      min_distance: DEFAULT_MIN_DISTANCE
+    }
+  }
+
+  pub fn with_min_distance(mut self, min_distance: f64) -> ColorAllocator {
+    self.min_distance = min_distance;
+    self
+  }
+
+  /// Marks a color as taken, so that it will never be handed out.
+  pub fn reserve(&mut self, color: [u8; 3]) {
+    self.taken.insert(color);
+  }
+
+  /// Marks the color of every definition in `defs` as taken.
+  pub fn reserve_defs(&mut self, defs: &[Def]) {
+    self.taken.extend(defs.iter().map(|def| def.rgb));
+  }
+
+  #[inline]
+  pub fn is_taken(&self, color: [u8; 3]) -> bool {
+    self.taken.contains(&color)
+  }
+
+  /// Returns a new color, or `None` if every color has been tried.
+  pub fn allocate(&mut self) -> Option<[u8; 3]> {
+    self.allocate_near(&[])
+  }
+
+  /// Returns a new color at least the minimum distance away from each of `neighbours`,
+  /// or `None` if every color has been tried.
+  pub fn allocate_near(&mut self, neighbours: &[[u8; 3]]) -> Option<[u8; 3]> {
+    while self.tried < COLOR_SPACE {
+      let color = self.next_candidate();
+      if self.taken.contains(&color) { continue };
+      if neighbours.iter().any(|&n| color_distance(color, n) < self.min_distance) { continue };
+      self.taken.insert(color);
+      return Some(color);
+    };
+
+    None
+  }
+
+  #[inline]
+  fn next_candidate(&mut self) -> [u8; 3] {
+    // An LCG modulo 2^24 with an odd increment and a multiplier of 1 mod 4 has a full period
+    self.state = self.state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223) % COLOR_SPACE;
+    self.tried += 1;
+    // The low bits of an LCG repeat quickly, so mix them with a bijection on 24 bits
+    let mut x = self.state;
+    x ^= x >> 12;
+    x = x.wrapping_mul(0x9e_37_79) % COLOR_SPACE;
+    x ^= x >> 11;
+    let [_, r, g, b] = x.to_be_bytes();
+    [r, g, b]
+  }
+}
+
+/// An approximation of how different two colors look, weighting each channel by how
+/// sensitive the eye is to it. Ranges from 0 for identical colors to about 765.
+pub fn color_distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+  let mean_r = (a[0] as f64 + b[0] as f64) / 2.0;
+  let dr = a[0] as f64 - b[0] as f64;
+  let dg = a[1] as f64 - b[1] as f64;
+  let db = a[2] as f64 - b[2] as f64;
+  ((2.0 + mean_r / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - mean_r) / 256.0) * db * db).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn same_seed_same_colors() {
+    let mut a = ColorAllocator::new(7);
+    let mut b = ColorAllocator::new(7);
+    let colors = (0..100).map(|_| a.allocate().unwrap()).collect::<Vec<_>>();
+    assert_eq!(colors, (0..100).map(|_| b.allocate().unwrap()).collect::<Vec<_>>());
+    assert_eq!(colors.iter().collect::<HashSet<_>>().len(), colors.len());
+  }
+
+  #[test]
+  fn taken_colors_are_skipped() {
+    let first = ColorAllocator::new(0).allocate().unwrap();
+    let mut allocator = ColorAllocator::new(0);
+    allocator.reserve(first);
+    let second = allocator.allocate().unwrap();
+    assert_ne!(second, first);
+    assert!(allocator.is_taken(second));
+    assert!(!RESERVED_COLORS.contains(&second));
+  }
+
+  #[test]
+  fn neighbours_are_kept_at_a_distance() {
+    let neighbours = [[128, 128, 128], [255, 0, 0], [0, 0, 255]];
+    let mut allocator = ColorAllocator::new(1).with_min_distance(200.0);
+    for _ in 0..20 {
+      let color = allocator.allocate_near(&neighbours).unwrap();
+      assert!(neighbours.iter().all(|&n| color_distance(color, n) >= 200.0));
+    };
+  }
+
+  #[test]
+  fn distance() {
+    assert_eq!(color_distance([10, 20, 30], [10, 20, 30]), 0.0);
+    assert_eq!(color_distance([0, 0, 0], [255, 0, 0]), color_distance([255, 0, 0], [0, 0, 0]));
+    assert!(color_distance([0, 0, 0], [0, 255, 0]) > color_distance([0, 0, 0], [0, 0, 255]));
+    assert!((color_distance([0, 0, 0], [255, 255, 255]) - 764.8).abs() < 0.1);
+  }
+}
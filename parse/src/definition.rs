@@ -1,11 +1,11 @@
 use regex::{Regex, Captures};
 
 use std::cmp::{Ord, PartialOrd, Ordering};
+use std::error::Error;
 use std::str::FromStr;
 use std::fmt;
 
 lazy_static!{
-  static ref RX_CSV: Regex = Regex::new(r"(\d+);(\d+);(\d+);(\d+);(\w+);(true|false);(\w+);(\d+)").unwrap();
   static ref RX_LOG: Regex = Regex::new(r"\[.+\]\[.+\]: Province (\d+) has no pixels in provinces\.bmp").unwrap();
   static ref RX_COLOR: Regex = Regex::new(r"\[(\d+),(\d+),(\d+)\]").unwrap();
 }
@@ -119,18 +119,74 @@ impl fmt::Display for Def {
 }
 
 impl FromStr for Def {
-  type Err = ();
+  type Err = CsvError;
 
-  fn from_str(s: &str) -> Result<Def, ()> {
-    parse_csv_line(s).ok_or(())
+  fn from_str(s: &str) -> Result<Def, CsvError> {
+    parse_csv_line(s, 1).map(|(def, _)| def)
   }
 }
 
-pub fn parse_csv(content: impl AsRef<str>) -> Option<Vec<Def>> {
-  let content = content.as_ref().trim();
-  content.split_whitespace()
-    .map(parse_csv_line)
-    .collect()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvError {
+  pub line: usize,
+  pub column: usize,
+  pub reason: CsvErrorReason
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvErrorReason {
+  FieldCount { expected: usize, found: usize },
+  InvalidId(String),
+  InvalidColor(String),
+  InvalidKind(String),
+  InvalidCoastal(String),
+  EmptyTerrain,
+  InvalidContinent(String),
+  InvalidProvince(String),
+  InvalidCoordinate(String)
+}
+
+impl fmt::Display for CsvError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, column {}: {}", self.line, self.column, self.reason)
+  }
+}
+
+impl fmt::Display for CsvErrorReason {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CsvErrorReason::FieldCount { expected, found } => {
+        write!(f, "expected at least {} fields, found {}", expected, found)
+      },
+      CsvErrorReason::InvalidId(s) => write!(f, "invalid id `{}`", s),
+      CsvErrorReason::InvalidColor(s) => write!(f, "invalid color component `{}`", s),
+      CsvErrorReason::InvalidKind(s) => write!(f, "invalid kind `{}`", s),
+      CsvErrorReason::InvalidCoastal(s) => write!(f, "invalid coastal flag `{}`", s),
+      CsvErrorReason::EmptyTerrain => write!(f, "terrain is empty"),
+      CsvErrorReason::InvalidContinent(s) => write!(f, "invalid continent `{}`", s),
+      CsvErrorReason::InvalidProvince(s) => write!(f, "invalid province id `{}`", s),
+      CsvErrorReason::InvalidCoordinate(s) => write!(f, "invalid coordinate `{}`", s)
+    }
+  }
+}
+
+impl Error for CsvError {}
+
+/// Parses the contents of a `definition.csv` file, stopping at the first malformed row.
+///
+/// Accepts LF or CRLF line endings, a leading UTF-8 BOM, blank lines and `#` comments.
+/// Columns after the eighth are ignored; use [`DefinitionFile`] to keep them.
+pub fn parse_csv(content: impl AsRef<str>) -> Result<Vec<Def>, CsvError> {
+  let content = content.as_ref();
+  let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+  let mut out = Vec::new();
+  for (i, line) in content.split('\n').enumerate() {
+    let line = strip_comment(line.strip_suffix('\r').unwrap_or(line));
+    if line.trim().is_empty() { continue };
+    out.push(parse_csv_line(line, i + 1)?.0);
+  };
+
+  Ok(out)
 }
 
 pub fn parse_csv_simple<'a>(content: impl AsRef<str>) -> Option<Vec<(usize, String)>> {
@@ -141,30 +197,72 @@ pub fn parse_csv_simple<'a>(content: impl AsRef<str>) -> Option<Vec<(usize, Stri
 }
 
 #[inline]
-fn parse_csv_line(line: &str) -> Option<Def> {
-  let captures = RX_CSV.captures(line)?;
-  Some(Def {
-    id: par(&captures, 1)?,
+pub(crate) fn strip_comment(line: &str) -> &str {
+  match line.find('#') {
+    Some(i) => &line[..i],
+    None => line
+  }
+}
+
+/// Parses a single row, returning the definition and any fields past the eighth, verbatim.
+pub(crate) fn parse_csv_line(line: &str, line_number: usize) -> Result<(Def, Vec<&str>), CsvError> {
+  let fields = split_fields(line);
+  if fields.len() < 8 {
+    let column = line.chars().count() + 1;
+    let reason = CsvErrorReason::FieldCount { expected: 8, found: fields.len() };
+    return Err(CsvError { line: line_number, column, reason });
+  };
+
+  let terrain = fields[6].1.trim();
+  if terrain.is_empty() {
+    let column = fields[6].0;
+    return Err(CsvError { line: line_number, column, reason: CsvErrorReason::EmptyTerrain });
+  };
+
+  let def = Def {
+    id: field(&fields, 0, line_number, CsvErrorReason::InvalidId)?,
     rgb: [
-      par(&captures, 2)?,
-      par(&captures, 3)?,
-      par(&captures, 4)?
+      field(&fields, 1, line_number, CsvErrorReason::InvalidColor)?,
+      field(&fields, 2, line_number, CsvErrorReason::InvalidColor)?,
+      field(&fields, 3, line_number, CsvErrorReason::InvalidColor)?
     ],
-    kind: par(&captures, 5)?,
-    coastal: par(&captures, 6)?,
-    terrain: own(&captures, 7),
-    continent: par(&captures, 8)?
-  })
+    kind: field(&fields, 4, line_number, CsvErrorReason::InvalidKind)?,
+    coastal: field(&fields, 5, line_number, CsvErrorReason::InvalidCoastal)?,
+    terrain: terrain.to_owned(),
+    continent: field(&fields, 7, line_number, CsvErrorReason::InvalidContinent)?
+  };
+
+  let extra = fields[8..].iter().map(|&(_, field)| field).collect();
+  Ok((def, extra))
 }
 
 #[inline]
-fn par<F: FromStr>(cap: &Captures, i: usize) -> Option<F> {
-  cap.get(i).unwrap().as_str().parse::<F>().ok()
+pub(crate) fn field<F: FromStr>(
+  fields: &[(usize, &str)],
+  i: usize,
+  line: usize,
+  reason: fn(String) -> CsvErrorReason
+) -> Result<F, CsvError> {
+  let (column, text) = fields[i];
+  let text = text.trim();
+  text.parse::<F>().map_err(|_| CsvError { line, column, reason: reason(text.to_owned()) })
+}
+
+/// Splits a line on `;`, pairing each field with its 1-based starting column.
+pub(crate) fn split_fields(line: &str) -> Vec<(usize, &str)> {
+  let mut fields = Vec::new();
+  let mut column = 1;
+  for field in line.split(';') {
+    fields.push((column, field));
+    column += field.chars().count() + 1;
+  };
+
+  fields
 }
 
 #[inline]
-fn own(cap: &Captures, i: usize) -> String {
-  cap.get(i).unwrap().as_str().to_owned()
+fn par<F: FromStr>(cap: &Captures, i: usize) -> Option<F> {
+  cap.get(i).unwrap().as_str().parse::<F>().ok()
 }
 
 #[inline]
@@ -0,0 +1,263 @@
+use std::str::FromStr;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+  Warning,
+  Error
+}
+
+impl Severity {
+  #[inline]
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Severity::Warning => "warning",
+      Severity::Error => "error"
+    }
+  }
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DiagnosticKind {
+  /// Two definitions share an id.
+  DuplicateId,
+  /// Two definitions share a color.
+  DuplicateColor,
+  /// An adjacency refers to a province with no definition.
+  UnknownProvince,
+  /// A sea crossing with no through province.
+  MissingThrough,
+  /// A sea crossing whose through province is not sea.
+  ThroughNotSea,
+  /// A color in the bitmap that no definition uses.
+  UndefinedColor,
+  /// A definition whose color does not appear in the bitmap.
+  NoPixels,
+  /// A 2x2 block where provinces meet only at a corner, either four different provinces or
+  /// one province on opposite corners crossed by another.
+  XCrossing,
+  /// A province with fewer pixels than the minimum size.
+  TooSmall,
+  /// A province with more pixels than the maximum size.
+  TooLarge,
+  /// A piece of a province that is cut off from its largest piece.
+  Disconnected,
+  /// A lake province marked as coastal.
+  CoastalLake,
+  /// A land province on continent 0.
+  NoContinent
+}
+
+impl DiagnosticKind {
+  #[inline]
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DiagnosticKind::DuplicateId => "duplicate_id",
+      DiagnosticKind::DuplicateColor => "duplicate_color",
+      DiagnosticKind::UnknownProvince => "unknown_province",
+      DiagnosticKind::MissingThrough => "missing_through",
+      DiagnosticKind::ThroughNotSea => "through_not_sea",
+      DiagnosticKind::UndefinedColor => "undefined_color",
+      DiagnosticKind::NoPixels => "no_pixels",
+      DiagnosticKind::XCrossing => "x_crossing",
+      DiagnosticKind::TooSmall => "too_small",
+      DiagnosticKind::TooLarge => "too_large",
+      DiagnosticKind::Disconnected => "disconnected",
+      DiagnosticKind::CoastalLake => "coastal_lake",
+      DiagnosticKind::NoContinent => "no_continent"
+    }
+  }
+
+  /// How serious this kind of problem is. Errors stop the game from loading the map
+  /// correctly, while warnings are only worth a look.
+  pub fn severity(&self) -> Severity {
+    match self {
+      DiagnosticKind::TooSmall |
+      DiagnosticKind::TooLarge |
+      DiagnosticKind::Disconnected |
+      DiagnosticKind::CoastalLake => Severity::Warning,
+      _ => Severity::Error
+    }
+  }
+}
+
+impl fmt::Display for DiagnosticKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// A single problem found while validating a map, along with the provinces, colors and
+/// pixel it involves.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+  pub kind: DiagnosticKind,
+  pub severity: Severity,
+  pub ids: Vec<usize>,
+  pub colors: Vec<[u8; 3]>,
+  pub position: Option<[u32; 2]>,
+  pub message: String
+}
+
+impl Diagnostic {
+  pub fn new(kind: DiagnosticKind, message: impl Into<String>) -> Diagnostic {
+    Diagnostic {
+      kind,
+      severity: kind.severity(),
+      ids: Vec::new(),
+      colors: Vec::new(),
+      position: None,
+      message: message.into()
+    }
+  }
+
+  pub fn with_ids(mut self, ids: impl IntoIterator<Item = usize>) -> Diagnostic {
+    self.ids.extend(ids);
+    self
+  }
+
+  pub fn with_colors(mut self, colors: impl IntoIterator<Item = [u8; 3]>) -> Diagnostic {
+    self.colors.extend(colors);
+    self
+  }
+
+  pub fn at(mut self, position: [u32; 2]) -> Diagnostic {
+    self.position = Some(position);
+    self
+  }
+
+  #[inline]
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.severity, self.message)?;
+    if let Some([x, y]) = self.position {
+      write!(f, " at ({}, {})", x, y)?;
+    };
+
+    Ok(())
+  }
+}
+
+/// Counts the errors and warnings in `diagnostics`, in that order.
+pub fn count_diagnostics(diagnostics: &[Diagnostic]) -> (usize, usize) {
+  let errors = diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count();
+  (errors, diagnostics.len() - errors)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+  Text,
+  Json,
+  Csv
+}
+
+impl FromStr for Format {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Format, ()> {
+    match s {
+      "text" => Ok(Format::Text),
+      "json" => Ok(Format::Json),
+      "csv" => Ok(Format::Csv),
+      _ => Err(())
+    }
+  }
+}
+
+pub fn render_diagnostics(diagnostics: &[Diagnostic], format: Format) -> String {
+  match format {
+    Format::Text => render_text(diagnostics),
+    Format::Json => render_json(diagnostics),
+    Format::Csv => render_csv(diagnostics)
+  }
+}
+
+/// One diagnostic per line, as it would be printed to a terminal.
+pub fn render_text(diagnostics: &[Diagnostic]) -> String {
+  diagnostics.iter().map(|diagnostic| format!("{}\n", diagnostic)).collect()
+}
+
+/// A JSON array with one object per diagnostic.
+pub fn render_json(diagnostics: &[Diagnostic]) -> String {
+  let mut out = String::from("[");
+  for (i, diagnostic) in diagnostics.iter().enumerate() {
+    let ids = diagnostic.ids.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let colors = diagnostic.colors.iter()
+      .map(|[r, g, b]| format!("[{},{},{}]", r, g, b))
+      .collect::<Vec<_>>();
+    let position = match diagnostic.position {
+      Some([x, y]) => format!("[{},{}]", x, y),
+      None => "null".to_owned()
+    };
+
+    out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+    out.push_str(&format!(
+      "{{\"severity\":\"{}\",\"kind\":\"{}\",\"ids\":[{}],\"colors\":[{}],\"position\":{},\"message\":\"{}\"}}",
+      diagnostic.severity,
+      diagnostic.kind,
+      ids.join(","),
+      colors.join(","),
+      position,
+      escape_json(&diagnostic.message)
+    ));
+  };
+
+  out.push_str(if diagnostics.is_empty() { "]\n" } else { "\n]\n" });
+  out
+}
+
+/// A `;`-separated report with a header row. Ids and colors are separated by spaces, and
+/// the position is left empty when there is none.
+pub fn render_csv(diagnostics: &[Diagnostic]) -> String {
+  let mut out = String::from("severity;kind;ids;colors;x;y;message\n");
+  for diagnostic in diagnostics {
+    let ids = diagnostic.ids.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let colors = diagnostic.colors.iter()
+      .map(|[r, g, b]| format!("{},{},{}", r, g, b))
+      .collect::<Vec<_>>();
+    let (x, y) = match diagnostic.position {
+      Some([x, y]) => (x.to_string(), y.to_string()),
+      None => (String::new(), String::new())
+    };
+
+    out.push_str(&format!(
+      "{};{};{};{};{};{};{}\n",
+      diagnostic.severity,
+      diagnostic.kind,
+      ids.join(" "),
+      colors.join(" "),
+      x, y,
+      diagnostic.message.replace(';', ",")
+    ));
+  };
+
+  out
+}
+
+fn escape_json(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c)
+    };
+  };
+
+  out
+}
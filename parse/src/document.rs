@@ -0,0 +1,370 @@
+use std::cmp::Ordering;
+use std::mem;
+use std::fmt;
+
+use crate::definition::*;
+use crate::remap::ProvinceMap;
+
+/// A `definition.csv` file that remembers everything it was parsed from.
+///
+/// Rows that are not edited are written back exactly as they were read, including any
+/// columns past the eighth, trailing comments, blank lines, comment lines, the line
+/// endings and the byte order mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionFile {
+  bom: bool,
+  newline: &'static str,
+  lines: Vec<Line>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+  Entry(Entry),
+  Other(String, &'static str)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+  def: Def,
+  original: Option<Def>,
+  raw: String,
+  extra: Vec<String>,
+  tail: String,
+  ending: &'static str
+}
+
+impl DefinitionFile {
+  pub fn new() -> DefinitionFile {
+    DefinitionFile {
+      bom: false,
+      newline: "\n",
+      lines: Vec::new()
+    }
+  }
+
+  pub fn parse(content: impl AsRef<str>) -> Result<DefinitionFile, CsvError> {
+    let content = content.as_ref();
+    let (bom, content) = match content.strip_prefix('\u{feff}') {
+      Some(content) => (true, content),
+      None => (false, content)
+    };
+
+    let mut newline = None;
+    let mut lines = Vec::new();
+    let mut rest = content;
+    let mut line_number = 0;
+    while !rest.is_empty() {
+      line_number += 1;
+      let (line, ending, next) = match rest.find('\n') {
+        Some(i) if rest[..i].ends_with('\r') => (&rest[..i - 1], "\r\n", &rest[i + 1..]),
+        Some(i) => (&rest[..i], "\n", &rest[i + 1..]),
+        None => (rest, "", "")
+      };
+
+      rest = next;
+      if !ending.is_empty() {
+        newline.get_or_insert(ending);
+      };
+
+      let body = strip_comment(line);
+      if body.trim().is_empty() {
+        lines.push(Line::Other(line.to_owned(), ending));
+        continue;
+      };
+
+      let body = body.trim_end();
+      let (def, extra) = parse_csv_line(body, line_number)?;
+      lines.push(Line::Entry(Entry {
+        original: Some(def.clone()),
+        def,
+        raw: line.to_owned(),
+        extra: extra.into_iter().map(str::to_owned).collect(),
+        tail: line[body.len()..].to_owned(),
+        ending
+      }));
+    };
+
+    Ok(DefinitionFile {
+      bom,
+      newline: newline.unwrap_or("\n"),
+      lines
+    })
+  }
+
+  pub fn len(&self) -> usize {
+    self.defs().count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.defs().next().is_none()
+  }
+
+  pub fn defs(&self) -> impl Iterator<Item = &Def> {
+    self.lines.iter().filter_map(|line| match line {
+      Line::Entry(entry) => Some(&entry.def),
+      Line::Other(..) => None
+    })
+  }
+
+  pub fn defs_mut(&mut self) -> impl Iterator<Item = &mut Def> {
+    self.lines.iter_mut().filter_map(|line| match line {
+      Line::Entry(entry) => Some(&mut entry.def),
+      Line::Other(..) => None
+    })
+  }
+
+  pub fn to_defs(&self) -> Vec<Def> {
+    self.defs().cloned().collect()
+  }
+
+  /// Appends a new row after the last line of the file.
+  pub fn push(&mut self, def: Def) {
+    self.terminate_last_line();
+    self.lines.push(Line::Entry(Entry::new(def, self.newline)));
+  }
+
+  /// Inserts a new row before every other row, after any leading comments.
+  pub fn push_front(&mut self, def: Def) {
+    let index = self.lines.iter()
+      .position(|line| matches!(line, Line::Entry(_)))
+      .unwrap_or(self.lines.len());
+    if index == self.lines.len() {
+      self.terminate_last_line();
+    };
+
+    self.lines.insert(index, Line::Entry(Entry::new(def, self.newline)));
+  }
+
+  /// Removes every row for which `f` returns false. Comment and blank lines are kept.
+  pub fn retain<F>(&mut self, mut f: F)
+  where F: FnMut(&Def) -> bool {
+    self.lines.retain(|line| match line {
+      Line::Entry(entry) => f(&entry.def),
+      Line::Other(..) => true
+    });
+  }
+
+  /// Stably sorts the rows among themselves. Comment and blank lines stay where they are,
+  /// while the rows' extra columns and trailing comments move along with them.
+  pub fn sort_by<F>(&mut self, mut f: F)
+  where F: FnMut(&Def, &Def) -> Ordering {
+    let mut slots = Vec::new();
+    let mut entries = Vec::new();
+    for (i, line) in self.lines.iter_mut().enumerate() {
+      if let Line::Entry(entry) = line {
+        slots.push((i, entry.ending));
+        entries.push(mem::replace(entry, Entry::new(Def::initial(), "")));
+      };
+    };
+
+    entries.sort_by(|a, b| f(&a.def, &b.def));
+
+    // Line endings belong to the position in the file, not to the row
+    for ((i, ending), mut entry) in Iterator::zip(slots.into_iter(), entries) {
+      entry.ending = ending;
+      self.lines[i] = Line::Entry(entry);
+    };
+  }
+
+  /// Removes every row for which `keep` returns false, then sorts the rest by type and
+  /// renumbers them from 0 so that no ids are skipped. The initial definition is always
+  /// kept, and added if it is missing.
+  /// Returns the new id of every row, with removed rows mapped to `None`.
+  pub fn collapse<F>(&mut self, mut keep: F) -> ProvinceMap
+  where F: FnMut(&Def) -> bool {
+    let mut map = ProvinceMap::new();
+    self.retain(|def| {
+      let kept = def.is_initial() || keep(def);
+      if !kept { map.insert(def.id, None) };
+      kept
+    });
+
+    self.sort_by(Def::cmp);
+
+    // A missing initial definition is only added once the rest are numbered, since it has no
+    // old id of its own and would otherwise take over the mapping of a row numbered 0
+    let missing_initial = !self.defs().any(Def::is_initial);
+    let first = if missing_initial { 1 } else { 0 };
+    for (i, def) in self.defs_mut().enumerate() {
+      map.insert(def.id, Some(first + i));
+      def.id = first + i;
+    };
+
+    if missing_initial {
+      self.push_front(Def::initial());
+    };
+
+    map
+  }
+
+  fn terminate_last_line(&mut self) {
+    let newline = self.newline;
+    match self.lines.last_mut() {
+      Some(Line::Entry(Entry { ending, .. })) |
+      Some(Line::Other(_, ending)) if ending.is_empty() => *ending = newline,
+      _ => ()
+    };
+  }
+}
+
+impl Default for DefinitionFile {
+  fn default() -> DefinitionFile {
+    DefinitionFile::new()
+  }
+}
+
+impl Entry {
+  fn new(def: Def, ending: &'static str) -> Entry {
+    Entry {
+      def,
+      original: None,
+      raw: String::new(),
+      extra: Vec::new(),
+      tail: String::new(),
+      ending
+    }
+  }
+}
+
+impl fmt::Display for DefinitionFile {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.bom {
+      write!(f, "\u{feff}")?;
+    };
+
+    for line in &self.lines {
+      match line {
+        Line::Entry(entry) => write!(f, "{}{}", entry, entry.ending)?,
+        Line::Other(text, ending) => write!(f, "{}{}", text, ending)?
+      };
+    };
+
+    Ok(())
+  }
+}
+
+impl fmt::Display for Entry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.original.as_ref() == Some(&self.def) {
+      return write!(f, "{}", self.raw);
+    };
+
+    let def = &self.def;
+    write!(
+      f, "{};{};{};{};{};{};{};{}",
+      def.id, def.rgb[0], def.rgb[1], def.rgb[2],
+      def.kind, def.coastal, def.terrain, def.continent
+    )?;
+
+    for extra in &self.extra {
+      write!(f, ";{}", extra)?;
+    };
+
+    write!(f, "{}", self.tail)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FILE: &str = "\u{feff}0;0;0;0;land;false;unknown;0\r\n\
+    # sea zones\r\n\
+    3;0;0;200;sea;false;ocean;0;extra;columns # tail\r\n\
+    \r\n\
+    1;10;0;0;land;true;plains;1\n\
+    2;20;0;0;lake;false;lakes;0  # spaced tail";
+
+  fn ids(file: &DefinitionFile) -> Vec<usize> {
+    file.defs().map(|def| def.id).collect()
+  }
+
+  #[test]
+  fn round_trip() {
+    let file = DefinitionFile::parse(FILE).unwrap();
+    assert_eq!(file.len(), 4);
+    assert_eq!(file.to_string(), FILE);
+  }
+
+  #[test]
+  fn edited_rows_keep_extra_columns_and_tails() {
+    let mut file = DefinitionFile::parse(FILE).unwrap();
+    for def in file.defs_mut() {
+      def.id += 10;
+    };
+
+    let expected = FILE
+      .replace("0;0;0;0;land", "10;0;0;0;land")
+      .replace("3;0;0;200", "13;0;0;200")
+      .replace("1;10;0;0", "11;10;0;0")
+      .replace("2;20;0;0", "12;20;0;0");
+    assert_eq!(file.to_string(), expected);
+  }
+
+  #[test]
+  fn push_uses_the_file_line_ending() {
+    let mut file = DefinitionFile::parse("0;0;0;0;land;false;unknown;0\r\n1;10;0;0;land;false;plains;1").unwrap();
+    file.push(Def { id: 2, rgb: [20, 0, 0], ..Def::initial() });
+    assert_eq!(file.to_string(), "0;0;0;0;land;false;unknown;0\r\n\
+      1;10;0;0;land;false;plains;1\r\n\
+      2;20;0;0;land;false;unknown;0\r\n");
+  }
+
+  #[test]
+  fn sort_keeps_comments_and_line_endings_in_place() {
+    let mut file = DefinitionFile::parse(FILE).unwrap();
+    file.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(ids(&file), [0, 1, 2, 3]);
+    assert_eq!(file.to_string(), "\u{feff}0;0;0;0;land;false;unknown;0\r\n\
+      # sea zones\r\n\
+      1;10;0;0;land;true;plains;1\r\n\
+      \r\n\
+      2;20;0;0;lake;false;lakes;0  # spaced tail\n\
+      3;0;0;200;sea;false;ocean;0;extra;columns # tail");
+  }
+
+  #[test]
+  fn collapse() {
+    let mut file = DefinitionFile::parse(FILE).unwrap();
+    let map = file.collapse(|def| def.id != 1);
+    assert_eq!(ids(&file), [0, 1, 2]);
+    let kinds = file.defs().map(|def| def.kind).collect::<Vec<_>>();
+    assert_eq!(kinds, [Kind::Land, Kind::Sea, Kind::Lake]);
+    assert_eq!(map.iter().collect::<Vec<_>>(), [(0, Some(0)), (1, None), (2, Some(2)), (3, Some(1))]);
+    assert_eq!(file.to_string(), "\u{feff}0;0;0;0;land;false;unknown;0\r\n\
+      # sea zones\r\n\
+      1;0;0;200;sea;false;ocean;0;extra;columns # tail\r\n\
+      \r\n\
+      2;20;0;0;lake;false;lakes;0  # spaced tail");
+  }
+
+  #[test]
+  fn collapse_adds_the_initial_definition() {
+    let mut file = DefinitionFile::parse("# header\n5;10;0;0;land;false;plains;1\n").unwrap();
+    let map = file.collapse(|_| true);
+    assert_eq!(file.to_string(), "# header\n0;0;0;0;land;false;unknown;0\n1;10;0;0;land;false;plains;1\n");
+    assert_eq!(map.get(5), Some(1));
+  }
+
+  #[test]
+  fn collapse_keeps_the_mapping_of_a_row_numbered_0() {
+    let content = "0;10;0;0;land;false;plains;1\n1;20;0;0;sea;false;ocean;0\n";
+    let mut file = DefinitionFile::parse(content).unwrap();
+    let map = file.collapse(|_| true);
+    assert_eq!(ids(&file), [0, 1, 2]);
+    assert_eq!(file.defs().nth(1).map(|def| def.rgb), Some([10, 0, 0]));
+    assert_eq!((map.get(0), map.get(1)), (Some(1), Some(2)));
+
+    let mut file = DefinitionFile::parse(content).unwrap();
+    let map = file.collapse(|def| def.id != 0);
+    assert_eq!(ids(&file), [0, 1]);
+    assert_eq!((map.get(0), map.get(1)), (None, Some(1)));
+  }
+
+  #[test]
+  fn parse_error_location() {
+    let content = "0;0;0;0;land;false;unknown;0\n# comment\n1;x;0;0;land;false;plains;1\n";
+    let err = DefinitionFile::parse(content).unwrap_err();
+    assert_eq!((err.line, err.column), (3, 3));
+  }
+}
@@ -2,10 +2,20 @@
 extern crate regex;
 
 mod validate;
+mod adjacency;
+mod color;
 mod definition;
+mod diagnostic;
+mod document;
+mod remap;
 
 pub use crate::validate::*;
+pub use crate::adjacency::*;
+pub use crate::color::*;
 pub use crate::definition::*;
+pub use crate::diagnostic::*;
+pub use crate::document::*;
+pub use crate::remap::*;
 
 #[macro_export]
 macro_rules! parallelize {
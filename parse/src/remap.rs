@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A mapping of old province ids to new ones, as produced by renumbering a `definition.csv`.
+///
+/// Ids that are not in the map are left as they are, while ids mapped to `None` belong to
+/// provinces that were removed. Provinces merged into another one are mapped to it, but
+/// remembered as merged, since they should not pull that province into their own state.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProvinceMap {
+  map: BTreeMap<usize, Option<usize>>,
+  merged: BTreeSet<usize>
+}
+
+impl ProvinceMap {
+  pub fn new() -> ProvinceMap {
+    ProvinceMap::default()
+  }
+
+  pub fn insert(&mut self, old: usize, new: Option<usize>) {
+    self.map.insert(old, new);
+    self.merged.remove(&old);
+  }
+
+  /// Maps `old` to `new` as a province that was merged into the province `new`.
+  pub fn insert_merged(&mut self, old: usize, new: usize) {
+    self.map.insert(old, Some(new));
+    self.merged.insert(old);
+  }
+
+  #[inline]
+  pub fn is_merged(&self, old: usize) -> bool {
+    self.merged.contains(&old)
+  }
+
+  /// Returns true if `old` has been given a mapping, even one that removes it.
+  #[inline]
+  pub fn contains(&self, old: usize) -> bool {
+    self.map.contains_key(&old)
+  }
+
+  #[inline]
+  pub fn get(&self, old: usize) -> Option<usize> {
+    self.map.get(&old).cloned().unwrap_or(Some(old))
+  }
+
+  /// Returns true if applying this map would not change any id.
+  pub fn is_identity(&self) -> bool {
+    self.map.iter().all(|(&old, &new)| new == Some(old))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (usize, Option<usize>)> + '_ {
+    self.map.iter().map(|(&old, &new)| (old, new))
+  }
+}
+
+/// Written as `old;new` lines, with an empty `new` for removed provinces.
+impl fmt::Display for ProvinceMap {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (old, new) in self.iter() {
+      match new {
+        Some(new) => writeln!(f, "{};{}", old, new)?,
+        None => writeln!(f, "{};", old)?
+      };
+    };
+
+    Ok(())
+  }
+}
+
+enum Edit {
+  Keep,
+  Replace(String),
+  Remove
+}
+
+/// Runs `f` over each line of `content`, keeping line endings and untouched lines intact.
+/// Returns the new content and how many lines were changed or removed.
+fn edit_lines<F>(content: &str, mut f: F) -> (String, usize)
+where F: FnMut(&str) -> Edit {
+  let mut out = String::with_capacity(content.len());
+  let mut changed = 0;
+  for line in content.split_inclusive('\n') {
+    let body = line.trim_end_matches(['\r', '\n']);
+    match f(body) {
+      Edit::Keep => out.push_str(line),
+      Edit::Replace(new) => {
+        changed += 1;
+        out.push_str(&new);
+        out.push_str(&line[body.len()..]);
+      },
+      Edit::Remove => changed += 1
+    };
+  };
+
+  (out, changed)
+}
+
+/// Remaps the `;`-separated fields at `columns`, leaving lines whose fields are not
+/// province ids (such as headers, or `-1` placeholders) alone. Lines referring to a
+/// removed province, or that `valid` rejects once remapped, are dropped.
+fn remap_columns<F, V>(content: &str, columns: &[usize], f: F, valid: V) -> (String, usize)
+where F: Fn(usize) -> Option<usize>, V: Fn(&[String]) -> bool {
+  edit_lines(content, |line| {
+    let mut fields = line.split(';').map(str::to_owned).collect::<Vec<_>>();
+    let mut edited = false;
+    for &column in columns {
+      let field = match fields.get_mut(column) {
+        Some(field) => field,
+        None => continue
+      };
+
+      let old = match field.trim().parse::<usize>() {
+        Ok(old) => old,
+        Err(_) => continue
+      };
+
+      match f(old) {
+        Some(new) if new == old => (),
+        Some(new) => { *field = new.to_string(); edited = true; },
+        None => return Edit::Remove
+      };
+    };
+
+    match edited {
+      true if !valid(&fields) => Edit::Remove,
+      true => Edit::Replace(fields.join(";")),
+      false => Edit::Keep
+    }
+  })
+}
+
+/// Remaps the `From`, `To` and `Through` columns of `map/adjacencies.csv`. Adjacencies left
+/// joining a province to itself, such as when provinces are merged, are dropped.
+pub fn remap_adjacencies(content: &str, map: &ProvinceMap) -> (String, usize) {
+  remap_columns(content, &[0, 1, 3], |id| map.get(id), |fields| {
+    fields.first().map(|field| field.trim()) != fields.get(1).map(|field| field.trim())
+  })
+}
+
+/// Remaps the adjacent sea province column of `map/buildings.txt`. A province id of `0`
+/// there means no sea province, and is left alone.
+pub fn remap_buildings(content: &str, map: &ProvinceMap) -> (String, usize) {
+  remap_columns(content, &[6], |id| if id == 0 { Some(0) } else { map.get(id) }, |_| true)
+}
+
+/// Remaps the province column of `map/unitstacks.txt`.
+pub fn remap_unitstacks(content: &str, map: &ProvinceMap) -> (String, usize) {
+  remap_columns(content, &[0], |id| map.get(id), |_| true)
+}
+
+/// Remaps the province column of `map/supply_nodes.txt`, which is written `level province`.
+pub fn remap_supply_nodes(content: &str, map: &ProvinceMap) -> (String, usize) {
+  edit_lines(content, |line| {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let old = match fields.get(1).and_then(|field| field.parse::<usize>().ok()) {
+      Some(old) => old,
+      None => return Edit::Keep
+    };
+
+    match map.get(old) {
+      Some(new) if new == old => Edit::Keep,
+      Some(new) => Edit::Replace(format!("{} {}", fields[0], new)),
+      None => Edit::Remove
+    }
+  })
+}
+
+/// Remaps `map/railways.txt`, where each line is `level count province...`. Removed provinces
+/// are taken out of the line, as are provinces repeated in a row after merging, and lines left
+/// with fewer than two provinces are dropped.
+pub fn remap_railways(content: &str, map: &ProvinceMap) -> (String, usize) {
+  edit_lines(content, |line| {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let provinces = match fields.get(2..) {
+      Some(provinces) if !provinces.is_empty() => provinces,
+      _ => return Edit::Keep
+    };
+
+    let old = match provinces.iter().map(|id| id.parse::<usize>()).collect::<Result<Vec<_>, _>>() {
+      Ok(old) => old,
+      Err(_) => return Edit::Keep
+    };
+
+    let mut new = old.iter().filter_map(|&id| map.get(id)).collect::<Vec<_>>();
+    new.dedup();
+    if new == old {
+      Edit::Keep
+    } else if new.len() < 2 {
+      Edit::Remove
+    } else {
+      let new = new.iter().map(ToString::to_string).collect::<Vec<_>>();
+      Edit::Replace(format!("{} {} {}", fields[0], new.len(), new.join(" ")))
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Removes 2, moves 3 to 2 and merges 4 into 1.
+  fn map() -> ProvinceMap {
+    let mut map = ProvinceMap::new();
+    map.insert(1, Some(1));
+    map.insert(2, None);
+    map.insert(3, Some(2));
+    map.insert_merged(4, 1);
+    map
+  }
+
+  #[test]
+  fn columns_keep_headers_and_line_endings() {
+    let content = "From;To;Type;Through\r\n1;3;sea;-1\r\n5;6;;-1\r\n3;2;;-1\n";
+    let (out, changed) = remap_columns(content, &[0, 1, 3], |id| map().get(id), |_| true);
+    assert_eq!(out, "From;To;Type;Through\r\n1;2;sea;-1\r\n5;6;;-1\r\n");
+    assert_eq!(changed, 2);
+  }
+
+  #[test]
+  fn adjacencies_to_themselves_are_dropped() {
+    let (out, changed) = remap_adjacencies("1;4;;-1\n1;3;;-1\n", &map());
+    assert_eq!(out, "1;2;;-1\n");
+    assert_eq!(changed, 2);
+  }
+
+  #[test]
+  fn buildings_keep_no_sea_province() {
+    let content = "1;arms_factory;1.0;2.0;3.0;0.0;0\n1;naval_base;1.0;2.0;3.0;0.0;3\n";
+    let (out, changed) = remap_buildings(content, &map());
+    assert_eq!(out, "1;arms_factory;1.0;2.0;3.0;0.0;0\n1;naval_base;1.0;2.0;3.0;0.0;2\n");
+    assert_eq!(changed, 1);
+  }
+
+  #[test]
+  fn supply_nodes() {
+    let (out, changed) = remap_supply_nodes("1 1\r\n1 2\r\n1 3\r\n", &map());
+    assert_eq!(out, "1 1\r\n1 2\r\n");
+    assert_eq!(changed, 2);
+  }
+
+  #[test]
+  fn railways_drop_removed_and_repeated_provinces() {
+    let content = "1 4 1 4 3 5\n2 2 2 3\n1 2 1 5\n";
+    let (out, changed) = remap_railways(content, &map());
+    assert_eq!(out, "1 3 1 2 5\n1 2 1 5\n");
+    assert_eq!(changed, 2);
+  }
+
+  #[test]
+  fn display() {
+    assert_eq!(map().to_string(), "1;1\n2;\n3;2\n4;1\n");
+  }
+}
@@ -1,70 +1,88 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::error::Error;
-use std::{fs, fmt, io};
+use std::{fs, io};
 
+use crate::adjacency::*;
 use crate::definition::*;
+use crate::diagnostic::*;
 
-//pub type ValidateError = (Option<(String, String)>, Vec<String>);
+/// Checks for duplicate ids and duplicate colors.
+pub fn validate_defs(defs: &[Def]) -> Vec<Diagnostic> {
+  let mut ids: HashMap<usize, &Def> = HashMap::new();
+  let mut colors: HashMap<[u8; 3], &Def> = HashMap::new();
+  let mut errors: Vec<Diagnostic> = Vec::new();
+
+  for def in defs {
+    ent(&mut ids, &def.id, def, |ent| {
+      errors.push(err_duplicate_thing(DiagnosticKind::DuplicateId, "id", ent, def));
+    });
 
-#[derive(Debug)]
-pub struct ValidateError {
-  pub write_result: Result<(), io::Error>,
-  pub invalid_items: Vec<String>
+    ent(&mut colors, &def.rgb, def, |ent| {
+      errors.push(err_duplicate_thing(DiagnosticKind::DuplicateColor, "color", ent, def));
+    });
+  };
+
+  errors
 }
 
-impl fmt::Display for ValidateError {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    if let Err(write_error) = &self.write_result {
-      writeln!(f, "{}", write_error);
+/// Checks that every province an adjacency refers to exists, and that sea crossings
+/// go through a sea province.
+pub fn validate_adjacencies(adjacencies: &[Adjacency], defs: &[Def]) -> Vec<Diagnostic> {
+  let kinds: HashMap<usize, Kind> = defs.iter().map(|def| (def.id, def.kind)).collect();
+  let mut errors: Vec<Diagnostic> = Vec::new();
+
+  for adjacency in adjacencies {
+    for id in adjacency.provinces() {
+      if !kinds.contains_key(&id) {
+        let message = format!(
+          "adjacency {}-{} refers to province {}, which does not exist",
+          adjacency.from, adjacency.to, id
+        );
+
+        errors.push(Diagnostic::new(DiagnosticKind::UnknownProvince, message)
+          .with_ids(vec![adjacency.from, adjacency.to, id]));
+      };
     };
 
-    for invalid in &self.invalid_items {
-      write!(f, "\n{}", invalid);
+    if adjacency.kind == AdjacencyKind::Sea {
+      match adjacency.through.map(|through| (through, kinds.get(&through))) {
+        None => {
+          let message = format!(
+            "sea crossing {}-{} has no through province",
+            adjacency.from, adjacency.to
+          );
+
+          errors.push(Diagnostic::new(DiagnosticKind::MissingThrough, message)
+            .with_ids(vec![adjacency.from, adjacency.to]));
+        },
+        Some((through, Some(&kind))) if kind != Kind::Sea => {
+          let message = format!(
+            "sea crossing {}-{} goes through province {}, which is {} rather than sea",
+            adjacency.from, adjacency.to, through, kind
+          );
+
+          errors.push(Diagnostic::new(DiagnosticKind::ThroughNotSea, message)
+            .with_ids(vec![adjacency.from, adjacency.to, through]));
+        },
+        Some(_) => ()
+      };
     };
+  };
 
-    Ok(())
-  }
+  errors
 }
 
-impl Error for ValidateError {}
-
-pub fn validate_defs(defs: &[Def], dump: bool) -> Result<(), ValidateError> {
-  let mut ids: HashMap<usize, &Def> = HashMap::new();
+/// Writes the colors shared by more than one definition to `dump_colors_conflicting.txt`,
+/// and every other color to `dump_colors.txt`.
+pub fn dump_duplicate_colors(defs: &[Def]) -> Result<(), io::Error> {
   let mut colors: HashMap<[u8; 3], &Def> = HashMap::new();
-  let mut errors: Vec<String> = Vec::new();
   let mut duplicate_colors = HashSet::new();
-
   for def in defs {
-    ent(&mut ids, &def.id, &def, |ent| {
-      err_duplicate_thing(&mut errors, "id", ent, def);
-    });
-
-    ent(&mut colors, &def.rgb, &def, |ent| {
+    ent(&mut colors, &def.rgb, def, |ent| {
       duplicate_colors.insert(ent.rgb);
-      err_duplicate_thing(&mut errors, "color", ent, def);
     });
   };
 
-  if errors.is_empty() {
-    Ok(())
-  } else {
-    let write_result = match dump {
-      true => dump_duplicate_colors(duplicate_colors, colors),
-      false => Ok(())
-    };
-    
-    Err(ValidateError {
-      write_result,
-      invalid_items: errors
-    })
-  }
-}
-
-fn dump_duplicate_colors(
-  duplicate_colors: HashSet<[u8; 3]>,
-  colors: HashMap<[u8; 3], &Def>
-) -> Result<(), io::Error> {
   let map_fn = |[r, g, b]: &[u8; 3]| format!("[{},{},{}] ", r, g, b);
   let mut conflicting_colors = duplicate_colors.iter()
     .map(map_fn).collect::<String>();
@@ -95,11 +113,15 @@ where T: Eq + Hash + Clone, F: FnMut(&'d Def) {
 }
 
 #[inline]
-fn err_duplicate_thing(errors: &mut Vec<String>, thing: &str, def1: &Def, def2: &Def) {
-  errors.push(format!(
+fn err_duplicate_thing(kind: DiagnosticKind, thing: &str, def1: &Def, def2: &Def) -> Diagnostic {
+  let message = format!(
     "duplicate {}s exist: {}={:?}, {}={:?}",
     thing,
     def1.id, def1.rgb,
     def2.id, def2.rgb
-  ));
+  );
+
+  Diagnostic::new(kind, message)
+    .with_ids(vec![def1.id, def2.id])
+    .with_colors(vec![def1.rgb, def2.rgb])
 }
@@ -0,0 +1,309 @@
+#[macro_use] extern crate util_macros;
+extern crate bitmap;
+extern crate image;
+extern crate parse;
+
+use bitmap::{BitmapError, Progress};
+use image::RgbImage;
+use parse::{CsvError, Def};
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::{fmt, fs, io};
+
+const BLACK: [u8; 3] = [0, 0, 0];
+const WHITE: [u8; 3] = [255, 255, 255];
+
+/// How many pixels of each old color ended up as each new color.
+type Pairs = HashMap<([u8; 3], [u8; 3]), u64>;
+
+fn main() {
+  match run() {
+    Err(Error::Bitmap(err)) => println!("error: unable to read or write image: {}", err),
+    Err(Error::Csv(err)) => println!("error: unable to parse definitions: {}", err),
+    Err(err) => println!("error: {:?}", err),
+    Ok(()) => ()
+  };
+}
+
+fn run() -> Result<(), Error> {
+  let old_defs = read_defs("definition_1.csv")?;
+  let new_defs = read_defs("definition_2.csv")?;
+  println!("definitions read ({} old provinces, {} new provinces)", old_defs.len(), new_defs.len());
+
+  let old_provs = bitmap::read_image("provinces_1.bmp")?;
+  let new_provs = bitmap::read_image("provinces_2.bmp")?;
+  println!("images read ({:?} old, {:?} new)", old_provs.dimensions(), new_provs.dimensions());
+
+  let comparison = compare(&old_provs, &new_provs);
+  println!("{} pixels changed", comparison.changed);
+
+  let changes = get_changes(&old_defs, &new_defs, &comparison.pairs);
+  for kind in ChangeKind::ALL {
+    let count = changes.iter().filter(|change| change.kind == kind).count();
+    println!("{} provinces {}", count, kind);
+  };
+
+  write_changes(&changes)?;
+  println!("changed provinces written to diff.csv");
+
+  bitmap::write_bmp("diff.bmp", &comparison.highlight)?;
+  println!("changed pixels written to diff.bmp");
+
+  Ok(())
+}
+
+struct Comparison {
+  pairs: Pairs,
+  /// Changed pixels in their new color, or white where they are now black.
+  highlight: RgbImage,
+  changed: u64
+}
+
+/// Zips the two images pixel by pixel, counting which colors became which. Wherever the images
+/// differ in size, the smaller one is treated as black past its edges.
+fn compare(old: &RgbImage, new: &RgbImage) -> Comparison {
+  let width = old.width().max(new.width());
+  let height = old.height().max(new.height());
+  let mut data = vec![0; width as usize * height as usize * 3];
+  let progress = Progress::new("comparing pixels", height as usize);
+  let results = bitmap::par_rows(&mut data, width as usize * 3, |first_row, data| {
+    let mut pairs = Pairs::new();
+    let mut changed = 0;
+    // Neighbouring pixels are usually the same pair, so count runs instead of hashing each one
+    let mut last = None;
+    let mut run = 0;
+    for (row, data) in data.chunks_mut(width as usize * 3).enumerate() {
+      let y = (first_row + row) as u32;
+      for (x, pixel) in data.chunks_mut(3).enumerate() {
+        let x = x as u32;
+        let pair = (pixel_at(old, x, y), pixel_at(new, x, y));
+        if pair.0 != pair.1 {
+          pixel.copy_from_slice(if pair.1 == BLACK { &WHITE } else { &pair.1 });
+          changed += 1;
+        };
+
+        if last == Some(pair) {
+          run += 1;
+        } else {
+          if let Some(last) = last {
+            *pairs.entry(last).or_insert(0) += run;
+          };
+
+          last = Some(pair);
+          run = 1;
+        };
+      };
+
+      progress.advance();
+    };
+
+    if let Some(last) = last {
+      *pairs.entry(last).or_insert(0) += run;
+    };
+
+    (pairs, changed)
+  });
+
+  let mut pairs = Pairs::new();
+  let mut changed = 0;
+  for (part_pairs, part_changed) in results {
+    for (key, pixels) in part_pairs {
+      *pairs.entry(key).or_insert(0) += pixels;
+    };
+
+    changed += part_changed;
+  };
+
+  let highlight = RgbImage::from_raw(width, height, data).unwrap();
+  Comparison { pairs, highlight, changed }
+}
+
+#[inline]
+fn pixel_at(img: &RgbImage, x: u32, y: u32) -> [u8; 3] {
+  if x < img.width() && y < img.height() {
+    img.get_pixel(x, y).0
+  } else {
+    BLACK
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+  Added,
+  Removed,
+  /// Kept its color, but has a different number of pixels.
+  Resized,
+  /// Kept its color and number of pixels, but not its shape.
+  Reshaped,
+  /// Has a new color, but the same shape.
+  Recolored,
+  /// Has the same color and shape, but a new id.
+  Renumbered
+}
+
+impl ChangeKind {
+  const ALL: [ChangeKind; 6] = [
+    ChangeKind::Added,
+    ChangeKind::Removed,
+    ChangeKind::Resized,
+    ChangeKind::Reshaped,
+    ChangeKind::Recolored,
+    ChangeKind::Renumbered
+  ];
+
+  fn as_str(self) -> &'static str {
+    match self {
+      ChangeKind::Added => "added",
+      ChangeKind::Removed => "removed",
+      ChangeKind::Resized => "resized",
+      ChangeKind::Reshaped => "reshaped",
+      ChangeKind::Recolored => "recolored",
+      ChangeKind::Renumbered => "renumbered"
+    }
+  }
+}
+
+impl fmt::Display for ChangeKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// A province as it was in one of the two maps.
+#[derive(Debug, Clone, Copy)]
+struct Side {
+  id: usize,
+  rgb: [u8; 3],
+  pixels: u64
+}
+
+#[derive(Debug, Clone)]
+struct Change {
+  kind: ChangeKind,
+  old: Option<Side>,
+  new: Option<Side>
+}
+
+/// Matches provinces between the two maps, first by color and then, for colors only found in one
+/// of them, by having exactly the same pixels. Unchanged provinces are left out.
+fn get_changes(old_defs: &[Def], new_defs: &[Def], pairs: &Pairs) -> Vec<Change> {
+  let mut old_pixels: HashMap<[u8; 3], u64> = HashMap::new();
+  let mut new_pixels: HashMap<[u8; 3], u64> = HashMap::new();
+  for (&(old, new), &pixels) in pairs {
+    *old_pixels.entry(old).or_insert(0) += pixels;
+    *new_pixels.entry(new).or_insert(0) += pixels;
+  };
+
+  let side = |def: &Def, pixels: &HashMap<[u8; 3], u64>| Side {
+    id: def.id,
+    rgb: def.rgb,
+    pixels: pixels.get(&def.rgb).copied().unwrap_or(0)
+  };
+
+  let old_defs = sorted_defs(old_defs);
+  let new_defs = sorted_defs(new_defs);
+  let new_by_color: HashMap<[u8; 3], &Def> = new_defs.iter().map(|&def| (def.rgb, def)).collect();
+  let old_colors: HashSet<[u8; 3]> = old_defs.iter().map(|def| def.rgb).collect();
+
+  // Each old color covered entirely by a single new color of the same size, which was not in the old map
+  let mut recolors = HashMap::new();
+  for (&(old, new), &pixels) in pairs {
+    if old != new && !old_colors.contains(&new) && new_by_color.contains_key(&new) &&
+      old_pixels.get(&old) == Some(&pixels) && new_pixels.get(&new) == Some(&pixels) {
+      recolors.insert(old, new);
+    };
+  };
+
+  let mut changes = Vec::new();
+  let mut matched = HashSet::new();
+  for def in old_defs {
+    let old = side(def, &old_pixels);
+    let (kind, new) = if let Some(&new_def) = new_by_color.get(&def.rgb) {
+      let new = side(new_def, &new_pixels);
+      let kept = pairs.get(&(def.rgb, def.rgb)).copied().unwrap_or(0);
+      let kind = if old.pixels != new.pixels {
+        ChangeKind::Resized
+      } else if kept != old.pixels {
+        ChangeKind::Reshaped
+      } else if old.id != new.id {
+        ChangeKind::Renumbered
+      } else {
+        matched.insert(new.rgb);
+        continue;
+      };
+
+      (kind, Some(new))
+    } else if let Some(new_def) = recolors.get(&def.rgb).map(|rgb| new_by_color[rgb]) {
+      (ChangeKind::Recolored, Some(side(new_def, &new_pixels)))
+    } else {
+      (ChangeKind::Removed, None)
+    };
+
+    if let Some(new) = &new {
+      matched.insert(new.rgb);
+    };
+
+    changes.push(Change { kind, old: Some(old), new });
+  };
+
+  for def in new_defs {
+    if !matched.contains(&def.rgb) {
+      changes.push(Change { kind: ChangeKind::Added, old: None, new: Some(side(def, &new_pixels)) });
+    };
+  };
+
+  changes
+}
+
+/// Definitions in id order, without the initial definition or any using a reserved color.
+fn sorted_defs(defs: &[Def]) -> Vec<&Def> {
+  let mut defs = defs.iter()
+    .filter(|def| def.rgb != BLACK && def.rgb != WHITE)
+    .collect::<Vec<_>>();
+  defs.sort_by_key(|def| def.id);
+  defs
+}
+
+/// Writes `diff.csv`, listing each changed province with its id, color and pixel count in both
+/// maps. Whichever side a province is missing from is left empty.
+fn write_changes(changes: &[Change]) -> Result<(), Error> {
+  let mut out = String::from("change;old_id;new_id;old_r;old_g;old_b;new_r;new_g;new_b;old_pixels;new_pixels;delta\n");
+  for change in changes {
+    let id = |side: Option<Side>| side.map_or(String::new(), |side| side.id.to_string());
+    let rgb = |side: Option<Side>| match side {
+      Some(Side { rgb: [r, g, b], .. }) => format!("{};{};{}", r, g, b),
+      None => ";;".to_owned()
+    };
+
+    let old_pixels = change.old.map_or(0, |side| side.pixels);
+    let new_pixels = change.new.map_or(0, |side| side.pixels);
+    out.push_str(&format!(
+      "{};{};{};{};{};{};{};{}\n",
+      change.kind,
+      id(change.old), id(change.new),
+      rgb(change.old), rgb(change.new),
+      change.old.map_or(String::new(), |side| side.pixels.to_string()),
+      change.new.map_or(String::new(), |side| side.pixels.to_string()),
+      new_pixels as i64 - old_pixels as i64
+    ));
+  };
+
+  fs::write("diff.csv", out)?;
+  Ok(())
+}
+
+fn read_defs<P: AsRef<Path>>(path: P) -> Result<Vec<Def>, Error> {
+  let data = fs::read_to_string(path)?;
+  let data = parse::parse_csv(data)?;
+  Ok(data)
+}
+
+error_enum!{
+  pub enum Error {
+    Io(io::Error),
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Custom(&'static str)
+  }
+}
@@ -0,0 +1,210 @@
+#[macro_use] extern crate util_macros;
+extern crate bitmap;
+extern crate image;
+extern crate parse;
+extern crate script;
+
+use bitmap::{BitmapError, GraphOptions, ProvinceGraph};
+use parse::{CsvError, Def, DefinitionFile, ProvinceMap};
+use script::RemapError;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::{fs, io};
+
+fn main() {
+  match run() {
+    Err(Error::Bitmap(err)) => println!("error: unable to read or write image: {}", err),
+    Err(Error::Csv(err)) => println!("error: unable to parse definition.csv: {}", err),
+    Err(Error::Remap(err)) => println!("error: unable to remap mod files: {}", err),
+    Err(err) => println!("error: {:?}", err),
+    Ok(()) => ()
+  };
+}
+
+fn run() -> Result<(), Error> {
+  let groups = read_groups()?;
+  if groups.is_empty() {
+    return Err("no provinces to merge, give them with --merge or in merges.txt".into());
+  };
+
+  let mut defs = DefinitionFile::parse(fs::read_to_string("definition.csv")?)?;
+  println!("definitions read from definition.csv ({} provinces)", defs.len());
+
+  let absorbed = resolve_groups(&groups)?;
+  let by_id: HashMap<usize, Def> = defs.defs().map(|def| (def.id, def.clone())).collect();
+  let mut colors = HashMap::new();
+  for (&id, &survivor) in &absorbed {
+    let (def, survivor) = match (by_id.get(&id), by_id.get(&survivor)) {
+      (Some(def), Some(survivor)) => (def, survivor),
+      _ => return Err("could not find every province to merge in definition.csv".into())
+    };
+
+    if def.is_initial() || survivor.is_initial() {
+      return Err("province 0 cannot be merged".into());
+    };
+
+    if def.kind != survivor.kind {
+      println!("warning: province {} ({}) merged into province {} ({})", def.id, def.kind, survivor.id, survivor.kind);
+    };
+
+    colors.insert(def.rgb, survivor.rgb);
+  };
+
+  let mut img = bitmap::read_image("provinces.bmp")?;
+  println!("image loaded from provinces.bmp");
+
+  let mut repainted = 0;
+  for pixel in img.pixels_mut() {
+    if let Some(&rgb) = colors.get(&pixel.0) {
+      pixel.0 = rgb;
+      repainted += 1;
+    };
+  };
+
+  println!("{} pixels repainted", repainted);
+
+  let collapsed = defs.collapse(|def| !absorbed.contains_key(&def.id));
+  let mut map = ProvinceMap::new();
+  for (old, new) in collapsed.iter() {
+    match absorbed.get(&old).and_then(|&survivor| collapsed.get(survivor)) {
+      Some(survivor) => map.insert_merged(old, survivor),
+      None => map.insert(old, new)
+    };
+  };
+
+  for (&id, &survivor) in &absorbed {
+    println!("province {} merged into province {}", id, survivor);
+  };
+
+  // Absorbing a sea province can also take the coast away from the provinces around it
+  let graph = ProvinceGraph::build(&img, &defs.to_defs(), GraphOptions::default());
+  let flipped = bitmap::recompute_coastal(&mut defs, &graph);
+  for def in defs.defs().filter(|def| flipped.contains(&def.id)) {
+    println!("province {}: coastal {} -> {}", def.id, !def.coastal, def.coastal);
+  };
+
+  println!("{} provinces merged away", absorbed.len());
+
+  fs::write("definition_new.csv", defs.to_string())?;
+  println!("new definitions written to definition_new.csv ({} provinces)", defs.len());
+
+  bitmap::write_bmp("provinces_new.bmp", &img)?;
+  println!("new provinces written to provinces_new.bmp");
+
+  fs::write("province_map.csv", map.to_string())?;
+  println!("province id mapping written to province_map.csv");
+
+  if let Some(mod_dir) = arg_value("--mod") {
+    remap_mod(&mod_dir, &map)?;
+  };
+
+  Ok(())
+}
+
+/// Reads every group given with `--merge <ids>` and every line of `merges.txt`. The first id
+/// of each group is the province the others are merged into.
+fn read_groups() -> Result<Vec<Vec<usize>>, Error> {
+  let mut groups = Vec::new();
+  for group in arg_values("--merge") {
+    groups.push(parse_group(&group).ok_or("invalid value for --merge")?);
+  };
+
+  if let Some(data) = read("merges.txt")? {
+    for line in data.lines().map(str::trim) {
+      if line.is_empty() || line.starts_with('#') { continue };
+      groups.push(parse_group(line).ok_or("unable to parse merges.txt")?);
+    };
+  };
+
+  Ok(groups)
+}
+
+/// Parses a list of at least two ids, separated by commas or whitespace.
+fn parse_group(group: &str) -> Option<Vec<usize>> {
+  let ids = group.split(|c: char| c == ',' || c.is_whitespace())
+    .filter(|id| !id.is_empty())
+    .map(|id| id.parse::<usize>().ok())
+    .collect::<Option<Vec<_>>>()?;
+  if ids.len() < 2 { None } else { Some(ids) }
+}
+
+/// Maps every absorbed province to the province it ends up part of, following chains such as
+/// `1,2` and `2,3` through to the end, so that 3 is merged into 1 as well.
+fn resolve_groups(groups: &[Vec<usize>]) -> Result<BTreeMap<usize, usize>, Error> {
+  let mut parents = BTreeMap::new();
+  for group in groups {
+    let survivor = group[0];
+    for &id in &group[1..] {
+      if id == survivor { continue };
+      match parents.insert(id, survivor) {
+        Some(other) if other != survivor => {
+          return Err("a province cannot be merged into more than one province".into());
+        },
+        _ => ()
+      };
+    };
+  };
+
+  let mut absorbed = BTreeMap::new();
+  for &id in parents.keys() {
+    let mut survivor = parents[&id];
+    let mut steps = 0;
+    while let Some(&parent) = parents.get(&survivor) {
+      survivor = parent;
+      steps += 1;
+      if steps > parents.len() {
+        return Err("provinces cannot be merged into each other in a loop".into());
+      };
+    };
+
+    absorbed.insert(id, survivor);
+  };
+
+  Ok(absorbed)
+}
+
+fn remap_mod(mod_dir: &str, map: &ProvinceMap) -> Result<(), Error> {
+  let out = arg_value("--mod-out").unwrap_or_else(|| "mod_new".to_owned());
+  let touched = script::remap_mod_dir(mod_dir, map, &out)?;
+  for (path, changed) in &touched {
+    println!("rewrote {} province references in {}", changed, path.display());
+  };
+
+  println!("province ids in {} remapped into {} ({} files changed)", mod_dir, out, touched.len());
+  Ok(())
+}
+
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+/// Every value given for a flag that can be repeated.
+fn arg_values(find: &str) -> Vec<String> {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  args.windows(2)
+    .filter(|pair| pair[0] == find)
+    .map(|pair| pair[1].clone())
+    .collect()
+}
+
+fn read<P: AsRef<Path>>(path: P) -> Result<Option<String>, io::Error> {
+  match fs::read_to_string(path) {
+    Ok(out) => Ok(Some(out)),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err)
+  }
+}
+
+error_enum!{
+  pub enum Error {
+    Io(io::Error),
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Remap(RemapError),
+    Custom(&'static str)
+  }
+}
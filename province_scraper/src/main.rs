@@ -1,71 +1,511 @@
 #[macro_use] extern crate util_macros;
+extern crate bitmap;
 extern crate image;
+extern crate parse;
+extern crate script;
 
-use image::{DynamicImage, RgbImage, Rgb};
-use image::codecs::{bmp::BmpDecoder, png::PngDecoder};
+use bitmap::{BitmapError, ColorGraph, ColorStats, Connectivity, GraphOptions, ProvinceGraph};
+use image::{GrayImage, RgbImage, Rgb};
+use parse::{CsvError, Def, DefinitionFile, Kind};
+use script::{RemapError, Script, Value};
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
 use std::{fs, io};
 
 fn main() {
-  run().unwrap();
+  match run() {
+    Err(Error::Bitmap(err)) => println!("error: unable to read image: {}", err),
+    Err(Error::Csv(err)) => println!("error: unable to parse definition.csv: {}", err),
+    Err(Error::Remap(err)) => println!("error: unable to read the terrain definitions: {}", err),
+    Err(err) => println!("error: {:?}", err),
+    Ok(()) => ()
+  };
 }
 
 fn run() -> Result<(), Error> {
-  let img = any(&[read_bmp, read_png][..])?;
-  println!("image loaded");
+  let (path, img) = bitmap::read_first(&["provinces.bmp", "provinces.png"])?;
+  println!("image loaded from {}", path.display());
+
+  if arg("--definition") {
+    return run_definition(&img);
+  };
+
+  if arg("--stats") {
+    return run_stats(&img);
+  };
+
+  run_colors(&img)
+}
+
+fn run_colors(img: &RgbImage) -> Result<(), Error> {
+  let format = match arg_value("--format") {
+    Some(format) => format.parse::<Format>().map_err(|_| "--format must be bracket, csv or json")?,
+    None => Format::Bracket
+  };
+
+  let order = match arg_value("--order") {
+    Some(order) => order.parse::<Order>().map_err(|_| "--order must be scan or color")?,
+    None => Order::Scan
+  };
 
-  let mut colors: HashSet<&Rgb<u8>> = img.pixels().collect();
-  colors.remove(&Rgb([0, 0, 0]));
-  colors.remove(&Rgb([255, 255, 255]));
+  let (ids, missing, extra) = (arg("--ids"), arg("--missing"), arg("--extra"));
+  let mut colors = scan_colors(img);
   println!("colors extracted");
 
-  let color_count = colors.len();
+  let mut defined = HashMap::new();
+  if ids || missing || extra {
+    let data = read("definition.csv")?
+      .ok_or("--ids, --missing and --extra need definition.csv")?;
+    let defs = DefinitionFile::parse(data)?;
+    println!("definitions read from definition.csv ({} provinces)", defs.len());
+
+    let mut defined_order = Vec::new();
+    for def in defs.defs().filter(|def| !is_reserved(def.rgb)) {
+      if defined.insert(def.rgb, def.id).is_none() {
+        defined_order.push(def.rgb);
+      };
+    };
+
+    if missing || extra {
+      let found: HashSet<[u8; 3]> = colors.iter().copied().collect();
+      colors.retain(|rgb| missing && !defined.contains_key(rgb));
+      colors.extend(defined_order.into_iter().filter(|rgb| extra && !found.contains(rgb)));
+    };
+  };
+
+  if order == Order::Color {
+    colors.sort_unstable();
+  };
+
+  let entries = colors.iter()
+    .map(|&rgb| (rgb, defined.get(&rgb).copied()))
+    .collect::<Vec<_>>();
+  let report = match format {
+    Format::Bracket => render_colors_bracket(&entries, ids),
+    Format::Csv => render_colors_csv(&entries, ids),
+    Format::Json => render_colors_json(&entries, ids)
+  };
+
+  let output = arg_value("--output").unwrap_or_else(|| format!("colors.{}", format.extension()));
+  fs::write(&output, report)?;
+  println!("colors written to {} ({} colors)", output, entries.len());
+
+  Ok(())
+}
+
+/// Space separated `[r,g,b]` tokens as read by province sniper, each prefixed with `id=` if it has one.
+fn render_colors_bracket(entries: &[([u8; 3], Option<usize>)], ids: bool) -> String {
+  entries.iter()
+    .map(|&([r, g, b], id)| match id {
+      Some(id) if ids => format!("{}=[{},{},{}]", id, r, g, b),
+      _ => format!("[{},{},{}]", r, g, b)
+    })
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+fn render_colors_csv(entries: &[([u8; 3], Option<usize>)], ids: bool) -> String {
+  let mut out = String::from(if ids { "r;g;b;id\n" } else { "r;g;b\n" });
+  for &([r, g, b], id) in entries {
+    match (ids, id) {
+      (true, Some(id)) => out.push_str(&format!("{};{};{};{}\n", r, g, b, id)),
+      (true, None) => out.push_str(&format!("{};{};{};\n", r, g, b)),
+      (false, _) => out.push_str(&format!("{};{};{}\n", r, g, b))
+    };
+  };
+
+  out
+}
 
-  let mut colors = colors.into_iter()
-    .map(|&Rgb([r, g, b])| format!("[{},{},{}] ", r, g, b))
-    .collect::<String>();
-  colors.pop(); // Remove last whitespace
+fn render_colors_json(entries: &[([u8; 3], Option<usize>)], ids: bool) -> String {
+  let mut out = String::from("[");
+  for (i, &([r, g, b], id)) in entries.iter().enumerate() {
+    out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+    match (ids, id) {
+      (true, Some(id)) => out.push_str(&format!("{{\"color\":[{},{},{}],\"id\":{}}}", r, g, b, id)),
+      (true, None) => out.push_str(&format!("{{\"color\":[{},{},{}],\"id\":null}}", r, g, b)),
+      (false, _) => out.push_str(&format!("{{\"color\":[{},{},{}]}}", r, g, b))
+    };
+  };
+
+  out.push_str(if entries.is_empty() { "]\n" } else { "\n]\n" });
+  out
+}
+
+fn run_definition(img: &RgbImage) -> Result<(), Error> {
+  let mut defs = match read("definition.csv")? {
+    Some(data) => {
+      let defs = DefinitionFile::parse(data)?;
+      println!("definitions read from definition.csv ({} provinces)", defs.len());
+      defs
+    },
+    None => {
+      let mut defs = DefinitionFile::new();
+      defs.push(Def::initial());
+      defs
+    }
+  };
+
+  let defaults = Defaults::open()?;
+  let known: HashSet<[u8; 3]> = defs.defs().map(|def| def.rgb).collect();
+  let mut colors = scan_colors(img);
+  colors.retain(|rgb| !known.contains(rgb));
+  println!("{} colors not yet in definition.csv", colors.len());
+
+  let inferred = if arg("--infer") {
+    Some(Inference::open(&defs)?.infer(img, &colors))
+  } else {
+    None
+  };
+
+  let first_id = defs.defs().map(|def| def.id + 1).max().unwrap_or(1);
+  let mut added = Vec::with_capacity(colors.len());
+  for (id, &rgb) in (first_id..).zip(&colors) {
+    let (terrain, continent) = inferred.as_ref()
+      .and_then(|inferred| inferred.get(&rgb).cloned())
+      .unwrap_or((None, None));
+
+    let terrain = terrain.unwrap_or_else(|| defaults.terrain.clone());
+    let kind = match terrain.as_str() {
+      "ocean" if inferred.is_some() => Kind::Sea,
+      "lakes" if inferred.is_some() => Kind::Lake,
+      _ => defaults.kind
+    };
+
+    let continent = match kind {
+      Kind::Land => continent.unwrap_or(defaults.continent),
+      _ => 0
+    };
+
+    added.push(Def { id, rgb, kind, coastal: false, terrain, continent });
+  };
+
+  if !added.is_empty() {
+    let mut list = defs.to_defs();
+    list.extend(added.iter().cloned());
+    let graph = ProvinceGraph::build(img, &list, GraphOptions::default());
+    let flags = bitmap::coastal_flags(&list, &graph);
+    for mut def in added {
+      def.coastal = flags.get(&def.id).copied().unwrap_or(false);
+      defs.push(def);
+    };
+  };
+
+  fs::write("definition_new.csv", defs.to_string())?;
+  println!("definitions written to definition_new.csv ({} provinces)", defs.len());
+
+  Ok(())
+}
+
+fn run_stats(img: &RgbImage) -> Result<(), Error> {
+  let format = match arg_value("--format") {
+    Some(format) => format.parse::<Format>().ok()
+      .filter(|&format| format != Format::Bracket)
+      .ok_or("--format must be csv or json")?,
+    None => Format::Csv
+  };
+
+  let mut options = GraphOptions::default();
+  if arg("--eight") {
+    options.connectivity = Connectivity::Eight;
+  };
+
+  if arg("--no-wrap") {
+    options.wrap_x = false;
+  };
+
+  let mut stats = bitmap::color_stats(img, options);
+  stats.remove(&[0, 0, 0]);
+  stats.remove(&[255, 255, 255]);
+  println!("statistics collected ({} colors)", stats.len());
+
+  let output = arg_value("--output").unwrap_or_else(|| format!("stats.{}", format.extension()));
+  let report = match format {
+    Format::Json => render_stats_json(&stats),
+    _ => render_stats_csv(&stats)
+  };
 
-  fs::write("colors.txt", colors)?;
-  println!("colors written to colors.txt ({} colors)", color_count);
+  fs::write(&output, report)?;
+  println!("statistics written to {}", output);
 
   Ok(())
 }
 
-fn read_bmp() -> Result<RgbImage, Error> {
-  let img_file = fs::File::open("provinces.bmp")?;
-  let img = BmpDecoder::new(img_file)?;
-  let img = DynamicImage::from_decoder(img)?;
-  Ok(img.into_rgb8())
+fn render_stats_csv(stats: &BTreeMap<[u8; 3], ColorStats>) -> String {
+  let mut out = String::from("r;g;b;pixels;min_x;min_y;max_x;max_y;centroid_x;centroid_y;pieces\n");
+  for (&[r, g, b], stats) in stats {
+    let [cx, cy] = stats.centroid();
+    out.push_str(&format!(
+      "{};{};{};{};{};{};{};{};{:.1};{:.1};{}\n",
+      r, g, b,
+      stats.pixels,
+      stats.bounds.min[0], stats.bounds.min[1],
+      stats.bounds.max[0], stats.bounds.max[1],
+      cx, cy,
+      stats.pieces.len()
+    ));
+  };
+
+  out
+}
+
+fn render_stats_json(stats: &BTreeMap<[u8; 3], ColorStats>) -> String {
+  let mut out = String::from("[");
+  for (i, (&[r, g, b], stats)) in stats.iter().enumerate() {
+    let [cx, cy] = stats.centroid();
+    out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+    out.push_str(&format!(
+      "{{\"color\":[{},{},{}],\"pixels\":{},\"min\":[{},{}],\"max\":[{},{}],\"centroid\":[{:.1},{:.1}],\"pieces\":{}}}",
+      r, g, b,
+      stats.pixels,
+      stats.bounds.min[0], stats.bounds.min[1],
+      stats.bounds.max[0], stats.bounds.max[1],
+      cx, cy,
+      stats.pieces.len()
+    ));
+  };
+
+  out.push_str(if stats.is_empty() { "]\n" } else { "\n]\n" });
+  out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+  Bracket,
+  Csv,
+  Json
+}
+
+impl Format {
+  fn extension(self) -> &'static str {
+    match self {
+      Format::Bracket => "txt",
+      Format::Csv => "csv",
+      Format::Json => "json"
+    }
+  }
+}
+
+impl FromStr for Format {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Format, ()> {
+    match s {
+      "bracket" => Ok(Format::Bracket),
+      "csv" => Ok(Format::Csv),
+      "json" => Ok(Format::Json),
+      _ => Err(())
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+  Scan,
+  Color
+}
+
+impl FromStr for Order {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Order, ()> {
+    match s {
+      "scan" => Ok(Order::Scan),
+      "color" => Ok(Order::Color),
+      _ => Err(())
+    }
+  }
+}
+
+/// Colors in the image other than black and white, in the order they are first found.
+fn scan_colors(img: &RgbImage) -> Vec<[u8; 3]> {
+  let mut seen = HashSet::new();
+  img.pixels()
+    .map(|&Rgb(rgb)| rgb)
+    .filter(|&rgb| !is_reserved(rgb) && seen.insert(rgb))
+    .collect()
+}
+
+#[inline]
+fn is_reserved(rgb: [u8; 3]) -> bool {
+  rgb == [0, 0, 0] || rgb == [255, 255, 255]
 }
 
-fn read_png() -> Result<RgbImage, Error> {
-  let img_file = fs::File::open("provinces.png")?;
-  let img = PngDecoder::new(img_file)?;
-  let img = DynamicImage::from_decoder(img)?;
-  Ok(img.into_rgb8())
+#[derive(Debug)]
+struct Defaults {
+  kind: Kind,
+  terrain: String,
+  continent: u32
 }
 
-fn any<T, E, F>(funcs: &[F]) -> Result<T, Vec<E>>
-where F: Fn() -> Result<T, E> {
-  let mut errors = Vec::new();
+impl Defaults {
+  fn open() -> Result<Defaults, Error> {
+    let kind = match arg_value("--kind") {
+      Some(kind) => kind.parse::<Kind>().map_err(|_| "--kind must be land, sea or lake")?,
+      None => Kind::Land
+    };
 
-  for f in funcs {
-    match f() {
-      Ok(t) => return Ok(t),
-      Err(t) => errors.push(t.into())
+    let continent = match arg_value("--continent") {
+      Some(continent) => continent.parse::<u32>().map_err(|_| "--continent must be a number")?,
+      None => 1
+    };
+
+    let terrain = arg_value("--terrain").unwrap_or_else(|| match kind {
+      Kind::Sea => "ocean".to_owned(),
+      Kind::Lake => "lakes".to_owned(),
+      _ => "plains".to_owned()
+    });
+
+    Ok(Defaults { kind, terrain, continent })
+  }
+}
+
+/// Majority terrain and continent of each new color, from `terrain.bmp` and the provinces
+/// around it.
+type Inferred = HashMap<[u8; 3], (Option<String>, Option<u32>)>;
+
+/// What `--infer` reads from: `terrain.bmp` along with the terrain each of its palette indices
+/// stands for, and the continent of each land province already in `definition.csv`.
+#[derive(Debug)]
+struct Inference {
+  terrain: Option<(GrayImage, HashMap<u8, String>)>,
+  continents: HashMap<[u8; 3], u32>
+}
+
+impl Inference {
+  fn open(defs: &DefinitionFile) -> Result<Inference, Error> {
+    let terrain = if Path::new("terrain.bmp").exists() {
+      let dir = arg_value("--terrain-dir").unwrap_or_else(|| "common/terrain".to_owned());
+      let table = read_terrain_indices(Path::new(&dir))?;
+      if table.is_empty() {
+        return Err("no terrain with a palette index found in the terrain directory".into());
+      };
+
+      println!("terrain will be inferred from terrain.bmp ({} palette indices from {})", table.len(), dir);
+      Some((bitmap::read_indexed("terrain.bmp")?, table))
+    } else {
+      println!("no terrain.bmp found, terrain will not be inferred");
+      None
+    };
+
+    let continents = defs.defs()
+      .filter(|def| def.kind == Kind::Land && def.continent != 0)
+      .map(|def| (def.rgb, def.continent))
+      .collect();
+    Ok(Inference { terrain, continents })
+  }
+
+  fn infer(&self, img: &RgbImage, colors: &[[u8; 3]]) -> Inferred {
+    let wanted: HashSet<[u8; 3]> = colors.iter().copied().collect();
+    let mut terrain_votes: HashMap<[u8; 3], BTreeMap<&str, u64>> = HashMap::new();
+    if let Some((terrain, table)) = &self.terrain {
+      for (x, y, &Rgb(rgb)) in img.enumerate_pixels() {
+        if !wanted.contains(&rgb) { continue };
+        if x >= terrain.width() || y >= terrain.height() { continue };
+        if let Some(name) = table.get(&terrain.get_pixel(x, y).0[0]) {
+          *terrain_votes.entry(rgb).or_default().entry(name.as_str()).or_insert(0) += 1;
+        };
+      };
+    };
+
+    // Each new province takes the continent it shares the longest border with
+    let graph = ColorGraph::from_image(img, GraphOptions::default());
+    colors.iter()
+      .map(|rgb| {
+        let terrain = terrain_votes.get(rgb).and_then(majority).map(str::to_owned);
+        let mut continent_votes = BTreeMap::new();
+        for (other, contacts) in graph.neighbours(*rgb) {
+          if let Some(&continent) = self.continents.get(&other) {
+            *continent_votes.entry(continent).or_insert(0) += contacts as u64;
+          };
+        };
+
+        (*rgb, (terrain, majority(&continent_votes)))
+      })
+      .collect()
+  }
+}
+
+/// The most common key, with ties going to the lowest.
+fn majority<K: Copy + Ord>(votes: &BTreeMap<K, u64>) -> Option<K> {
+  votes.iter()
+    .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+    .map(|(&key, _)| key)
+}
+
+/// Reads the terrain each palette index of `terrain.bmp` stands for from the `terrain` block
+/// of every file in `common/terrain`, where each entry lists its indices as `color = { ... }`.
+/// Entries are taken as the terrain category given by their `type`, or their own name if
+/// they have none, which is what `definition.csv` refers to.
+fn read_terrain_indices(dir: &Path) -> Result<HashMap<u8, String>, Error> {
+  let entries = fs::read_dir(dir).map_err(|err| RemapError::Io(dir.to_owned(), err))?;
+  let mut paths = Vec::new();
+  for entry in entries {
+    let path = entry.map_err(|err| RemapError::Io(dir.to_owned(), err))?.path();
+    if path.extension() == Some("txt".as_ref()) {
+      paths.push(path);
     };
   };
 
-  Err(errors)
+  paths.sort();
+  let mut table = HashMap::new();
+  for path in paths {
+    let content = fs::read_to_string(&path).map_err(|err| RemapError::Io(path.clone(), err))?;
+    let script = Script::parse(&content).map_err(|err| RemapError::Script(path.clone(), err))?;
+    table.extend(terrain_indices(&script));
+  };
+
+  Ok(table)
+}
+
+fn terrain_indices(script: &Script) -> Vec<(u8, String)> {
+  let mut indices = Vec::new();
+  for terrain in script.get_all("terrain").filter_map(Value::as_block) {
+    for field in terrain.fields() {
+      let entry = match field.value.as_block() {
+        Some(entry) => entry,
+        None => continue
+      };
+
+      let name = entry.get_scalar("type").unwrap_or(&field.key).as_str();
+      let color = entry.get_block("color").into_iter().flat_map(|color| color.values());
+      for index in color.filter_map(Value::as_scalar).filter_map(|index| index.parse::<u8>()) {
+        indices.push((index, name.to_owned()));
+      };
+    };
+  };
+
+  indices
+}
+
+#[inline]
+fn arg(find: &str) -> bool {
+  std::env::args().skip(1).any(|a| a == find)
+}
+
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+fn read<P: AsRef<Path>>(path: P) -> Result<Option<String>, io::Error> {
+  match fs::read_to_string(path) {
+    Ok(out) => Ok(Some(out)),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err)
+  }
 }
 
 error_enum!{
   pub enum Error {
     Io(io::Error),
-    Image(image::ImageError),
-    Custom(&'static str),
-    Many(Vec<Error>)
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Remap(RemapError),
+    Custom(&'static str)
   }
 }
@@ -1,7 +1,12 @@
 #[macro_use] extern crate util_macros;
+extern crate bitmap;
+extern crate image;
 extern crate parse;
+extern crate script;
 
-use parse::{Def, Kind, ValidateError};
+use bitmap::{BitmapError, GraphOptions, ProvinceGraph};
+use parse::{CsvError, Def, DefinitionFile, Diagnostic, Kind, ProvinceMap};
+use script::RemapError;
 
 use std::collections::BTreeSet;
 use std::path::Path;
@@ -9,29 +14,66 @@ use std::{io, fs, fmt};
 
 fn main() {
   match run() {
-    Err(Error::Validation(errors)) => println!("error: validation failed\n{}", errors),
+    Err(Error::Validation(errors)) => print!("error: validation failed\n{}", parse::render_text(&errors)),
+    Err(Error::Csv(err)) => println!("error: unable to parse definition.csv: {}", err),
+    Err(Error::Remap(err)) => println!("error: unable to remap mod files: {}", err),
+    Err(Error::Bitmap(err)) => println!("error: unable to read image: {}", err),
     Err(err) => println!("error: {:?}", err),
     _ => {}
   };
 }
 
 fn run() -> Result<(), Error> {
+  if arg("--coastal") {
+    return run_coastal();
+  };
+
   let rule = Rule::open()?;
   println!("definition rule: {}", rule);
 
-  let defs = read_definition()?;
+  let mut defs = read_definition()?;
   println!("definitions read from definition.csv ({} provinces)", defs.len());
 
-  conditional_validation(&defs, arg("--validate"))?;
+  conditional_validation(&defs.to_defs(), arg("--validate"))?;
 
-  let (defs, removed) = create_definitions(defs, |def| rule.apply(def));
+  let (removed, map) = create_definitions(&mut defs, |def| rule.apply(def));
   println!("new definitions created, {} provinces removed", removed);
 
-  conditional_validation(&defs, arg("--post-validate"))?;
+  conditional_validation(&defs.to_defs(), arg("--post-validate"))?;
 
   write_definition(&defs)?;
   println!("new definitions written to definition_new.csv ({} provinces)", defs.len());
 
+  fs::write("province_map.csv", map.to_string())?;
+  println!("province id mapping written to province_map.csv");
+
+  if let Some(mod_dir) = arg_value("--mod") {
+    remap_mod(&mod_dir, &map)?;
+  };
+
+  Ok(())
+}
+
+fn run_coastal() -> Result<(), Error> {
+  let mut defs = read_definition()?;
+  println!("definitions read from definition.csv ({} provinces)", defs.len());
+
+  let img = bitmap::read_image("provinces.bmp")?;
+  println!("image loaded from provinces.bmp");
+
+  let graph = ProvinceGraph::build(&img, &defs.to_defs(), GraphOptions::default());
+  let flipped = bitmap::recompute_coastal(&mut defs, &graph);
+  for def in defs.defs().filter(|def| flipped.contains(&def.id)) {
+    println!("province {}: coastal {} -> {}", def.id, !def.coastal, def.coastal);
+  };
+
+  println!("coastal flags recomputed, {} land provinces flipped", flipped.len());
+
+  if arg("--write") {
+    write_definition(&defs)?;
+    println!("corrected definitions written to definition_new.csv");
+  };
+
   Ok(())
 }
 
@@ -40,9 +82,40 @@ fn arg(find: &str) -> bool {
   std::env::args().skip(1).any(|a| a == find)
 }
 
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+fn remap_mod(mod_dir: &str, map: &ProvinceMap) -> Result<(), Error> {
+  if map.is_identity() {
+    println!("no province ids changed, {} left as it is", mod_dir);
+    return Ok(());
+  };
+
+  let out = arg_value("--mod-out").unwrap_or_else(|| "mod_new".to_owned());
+  let touched = script::remap_mod_dir(mod_dir, map, &out)?;
+  for (path, changed) in &touched {
+    println!("rewrote {} province references in {}", changed, path.display());
+  };
+
+  println!("province ids in {} remapped into {} ({} files changed)", mod_dir, out, touched.len());
+  Ok(())
+}
+
 fn conditional_validation(definitions: &[Def], condition: bool) -> Result<(), Error> {
   if condition {
-    parse::validate_defs(&definitions, arg("--dump-validate"))?;
+    let diagnostics = parse::validate_defs(definitions);
+    if !diagnostics.is_empty() {
+      if arg("--dump-validate") {
+        parse::dump_duplicate_colors(definitions)?;
+      };
+
+      return Err(Error::Validation(diagnostics));
+    };
+
     println!("no duplicate ids or colors");
   } else {
     println!("no validation performed");
@@ -51,43 +124,24 @@ fn conditional_validation(definitions: &[Def], condition: bool) -> Result<(), Er
   Ok(())
 }
 
-fn create_definitions<F>(definitions: Vec<Def>, mut func: F) -> (Vec<Def>, usize)
-where F: FnMut(&mut Def) -> bool {
-  let mut removed = 0;
-  let mut new_definitions = Vec::new();
+fn create_definitions<F>(definitions: &mut DefinitionFile, mut func: F) -> (usize, ProvinceMap)
+where F: FnMut(&Def) -> bool {
   let keep_lakes = arg("--keep-lakes");
-  new_definitions.push(Def::initial());
-  for mut def in definitions {
-    if def.kind != Kind::Unknown {
-      if func(&mut def) || (def.kind != Kind::Lake && keep_lakes) {
-        //def.id = new_definitions.len();
-        new_definitions.push(def);
-      } else {
-        removed += 1;
-      };
-    };
-  };
+  let map = definitions.collapse(|def| {
+    def.kind != Kind::Unknown && (func(def) || (def.kind != Kind::Lake && keep_lakes))
+  });
 
-  new_definitions.sort();
-
-  for (i, def) in new_definitions.iter_mut().enumerate() {
-    def.id = i;
-  };
-  
-  (new_definitions, removed)
+  let removed = map.iter().filter(|(_, new)| new.is_none()).count();
+  (removed, map)
 }
 
-fn write_definition(defs: &[Def]) -> Result<(), Error> {
-  let defs = defs.iter().map(|e| e.to_string()).collect::<String>();
-  fs::write("definition_new.csv", defs).map_err(From::from)
+fn write_definition(defs: &DefinitionFile) -> Result<(), Error> {
+  fs::write("definition_new.csv", defs.to_string()).map_err(From::from)
 }
 
-fn read_definition() -> Result<Vec<Def>, Error> {
+fn read_definition() -> Result<DefinitionFile, Error> {
   match read("definition.csv") {
-    Ok(Some(data)) => match parse::parse_csv(data) {
-      Some(data) => Ok(data),
-      None => Err("unable to parse definition.csv".into())
-    },
+    Ok(Some(data)) => DefinitionFile::parse(data).map_err(From::from),
     Ok(None) => Err("could not find definition.csv".into()),
     Err(err) => Err(err.into())
   }
@@ -222,7 +276,10 @@ impl Criteria {
 error_enum!{
   enum Error {
     Io(io::Error),
-    Validation(ValidateError),
+    Bitmap(BitmapError),
+    Validation(Vec<Diagnostic>),
+    Csv(CsvError),
+    Remap(RemapError),
     Custom(&'static str)
   }
 }
@@ -0,0 +1,312 @@
+#[macro_use] extern crate util_macros;
+extern crate bitmap;
+extern crate image;
+extern crate parse;
+extern crate script;
+
+use bitmap::{BitmapError, ColorGraph, GraphOptions, ProvinceGraph};
+use image::Rgb;
+use parse::{ColorAllocator, CsvError, Def, DefinitionFile};
+use script::RemapError;
+
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, io};
+
+/// k-means almost always settles long before this, so it is only here to guarantee an end.
+const MAX_ITERATIONS: usize = 100;
+
+fn main() {
+  match run() {
+    Err(Error::Bitmap(err)) => println!("error: unable to read or write image: {}", err),
+    Err(Error::Csv(err)) => println!("error: unable to parse definition.csv: {}", err),
+    Err(Error::Remap(err)) => println!("error: unable to update mod files: {}", err),
+    Err(err) => println!("error: {:?}", err),
+    Ok(()) => ()
+  };
+}
+
+fn run() -> Result<(), Error> {
+  let id = arg_value("--province").ok_or("missing --province <id>")?
+    .parse::<usize>().map_err(|_| "invalid value for --province")?;
+  let count = arg_value("--count").ok_or("missing --count <n>")?
+    .parse::<usize>().map_err(|_| "invalid value for --count")?;
+  if count < 2 {
+    return Err("--count must be at least 2".into());
+  };
+
+  let seed = match arg_value("--seed") {
+    Some(seed) => seed.parse::<u64>().map_err(|_| "invalid value for --seed")?,
+    None => 0
+  };
+
+  let mut defs = DefinitionFile::parse(fs::read_to_string("definition.csv")?)?;
+  println!("definitions read from definition.csv ({} provinces)", defs.len());
+
+  let original = defs.defs().find(|def| def.id == id).cloned()
+    .ok_or("could not find the province in definition.csv")?;
+  if original.is_initial() {
+    return Err("province 0 cannot be split".into());
+  };
+
+  let mut img = bitmap::read_image("provinces.bmp")?;
+  println!("image loaded from provinces.bmp");
+
+  let pixels = img.enumerate_pixels()
+    .filter(|(_, _, pixel)| pixel.0 == original.rgb)
+    .map(|(x, y, _)| [x, y])
+    .collect::<Vec<_>>();
+  if pixels.len() < count {
+    return Err("the province has fewer pixels than parts to split it into".into());
+  };
+
+  let mut labels = k_means(&pixels, count);
+  make_contiguous(&pixels, &mut labels);
+
+  let mut sizes = vec![0u64; count];
+  for &label in &labels {
+    sizes[label] += 1;
+  };
+
+  // The largest part keeps the original id and color, and empty parts are dropped
+  let mut parts = (0..count).filter(|&part| sizes[part] > 0).collect::<Vec<_>>();
+  parts.sort_by_key(|&part| std::cmp::Reverse(sizes[part]));
+
+  let graph = ColorGraph::from_image(&img, GraphOptions::default());
+  let mut allocator = ColorAllocator::new(seed);
+  allocator.reserve_defs(&defs.to_defs());
+  for color in graph.colors() {
+    allocator.reserve(color);
+  };
+
+  let mut neighbours = graph.neighbours(original.rgb)
+    .map(|(color, _)| color)
+    .collect::<Vec<_>>();
+  neighbours.push(original.rgb);
+
+  let first_id = defs.defs().map(|def| def.id + 1).max().unwrap_or(1);
+  let mut provinces = vec![(id, original.rgb); count];
+  let mut new_ids = Vec::with_capacity(parts.len() - 1);
+  for (next_id, &part) in (first_id..).zip(&parts[1..]) {
+    let rgb = allocator.allocate_near(&neighbours)
+      .ok_or("ran out of colors to give new provinces")?;
+    neighbours.push(rgb);
+    provinces[part] = (next_id, rgb);
+    new_ids.push(next_id);
+    defs.push(Def { id: next_id, rgb, ..original.clone() });
+  };
+
+  for (&[x, y], &label) in Iterator::zip(pixels.iter(), labels.iter()) {
+    img.put_pixel(x, y, Rgb(provinces[label].1));
+  };
+
+  let list = defs.to_defs();
+  let graph = ProvinceGraph::build(&img, &list, GraphOptions::default());
+  let flags = bitmap::coastal_flags(&list, &graph);
+  for def in defs.defs_mut().filter(|def| def.id == id || new_ids.contains(&def.id)) {
+    if let Some(&coastal) = flags.get(&def.id) {
+      def.coastal = coastal;
+    };
+  };
+
+  for &part in &parts {
+    let (id, [r, g, b]) = provinces[part];
+    println!("province {}: [{},{},{}] ({} pixels)", id, r, g, b, sizes[part]);
+  };
+
+  println!("province {} split into {} provinces", id, parts.len());
+
+  fs::write("definition_new.csv", defs.to_string())?;
+  println!("new definitions written to definition_new.csv ({} provinces)", defs.len());
+
+  bitmap::write_bmp("provinces_new.bmp", &img)?;
+  println!("new provinces written to provinces_new.bmp");
+
+  if let Some(mod_dir) = arg_value("--mod") {
+    let touched = script::add_split_provinces(&mod_dir, id, &new_ids)?;
+    for (path, added) in &touched {
+      println!("added {} provinces to {}", added, path.display());
+    };
+
+    println!("states and strategic regions updated in {} ({} files changed)", mod_dir, touched.len());
+  };
+
+  Ok(())
+}
+
+/// Groups `pixels` into `count` clusters of nearby pixels, returning the cluster of each pixel.
+/// Clusters start from pixels spread as far apart as possible, so the result is always the same.
+fn k_means(pixels: &[[u32; 2]], count: usize) -> Vec<usize> {
+  let points = pixels.iter()
+    .map(|&[x, y]| [x as f64, y as f64])
+    .collect::<Vec<_>>();
+  let mean = points.iter().fold([0.0, 0.0], |[sx, sy], [x, y]| [sx + x, sy + y]);
+  let mean = [mean[0] / points.len() as f64, mean[1] / points.len() as f64];
+
+  let first = farthest(points.iter().map(|&point| distance(point, mean)));
+  let mut centers = vec![points[first]];
+  let mut nearest = points.iter()
+    .map(|&point| distance(point, points[first]))
+    .collect::<Vec<_>>();
+  while centers.len() < count {
+    let next = points[farthest(nearest.iter().copied())];
+    centers.push(next);
+    for (nearest, &point) in Iterator::zip(nearest.iter_mut(), points.iter()) {
+      *nearest = nearest.min(distance(point, next));
+    };
+  };
+
+  let mut labels = vec![usize::MAX; points.len()];
+  for _ in 0..MAX_ITERATIONS {
+    let mut changed = false;
+    for (label, &point) in Iterator::zip(labels.iter_mut(), points.iter()) {
+      let closest = (0..count)
+        .min_by(|&a, &b| distance(point, centers[a]).total_cmp(&distance(point, centers[b])))
+        .unwrap();
+      if *label != closest {
+        *label = closest;
+        changed = true;
+      };
+    };
+
+    if !changed { break };
+
+    let mut sums = vec![[0.0, 0.0, 0.0]; count];
+    for (&label, &[x, y]) in Iterator::zip(labels.iter(), points.iter()) {
+      sums[label][0] += x;
+      sums[label][1] += y;
+      sums[label][2] += 1.0;
+    };
+
+    for (center, &[sx, sy, n]) in Iterator::zip(centers.iter_mut(), sums.iter()) {
+      if n > 0.0 {
+        *center = [sx / n, sy / n];
+      };
+    };
+  };
+
+  labels
+}
+
+/// The index of the largest of `distances`, the first one winning ties.
+fn farthest(distances: impl Iterator<Item = f64>) -> usize {
+  distances.enumerate()
+    .fold((0, f64::MIN), |best, (i, d)| if d > best.1 { (i, d) } else { best })
+    .0
+}
+
+/// Squared distance, which is all that is needed for comparisons.
+#[inline]
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+  (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)
+}
+
+/// Gives every stray piece of a cluster, other than its largest, to whichever cluster it shares
+/// the longest border with, until every cluster is in one piece. Pieces that touch no other
+/// cluster, which only happens if the province was already in pieces, are left alone.
+fn make_contiguous(pixels: &[[u32; 2]], labels: &mut [usize]) {
+  let index: HashMap<[u32; 2], usize> = pixels.iter()
+    .enumerate()
+    .map(|(i, &pixel)| (pixel, i))
+    .collect();
+
+  loop {
+    let pieces = find_pieces(pixels, labels, &index);
+    let mut largest: HashMap<usize, usize> = HashMap::new();
+    for (i, piece) in pieces.iter().enumerate() {
+      let best = largest.entry(labels[piece[0]]).or_insert(i);
+      if piece.len() > pieces[*best].len() {
+        *best = i;
+      };
+    };
+
+    // Borders only count against the largest piece of each cluster, which never moves, so every
+    // stray piece can be handed over in the same pass without them chasing each other around
+    let mut main = vec![false; pixels.len()];
+    for &best in largest.values() {
+      for &i in &pieces[best] {
+        main[i] = true;
+      };
+    };
+
+    let moves = pieces.iter().enumerate()
+      .filter(|&(i, piece)| largest[&labels[piece[0]]] != i)
+      .filter_map(|(_, piece)| {
+        let label = labels[piece[0]];
+        let mut borders: BTreeMap<usize, usize> = BTreeMap::new();
+        for &i in piece {
+          for neighbour in neighbours(pixels[i]) {
+            match index.get(&neighbour) {
+              Some(&j) if main[j] && labels[j] != label => *borders.entry(labels[j]).or_insert(0) += 1,
+              _ => ()
+            };
+          };
+        };
+
+        let (&new, _) = borders.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))?;
+        Some((piece, new))
+      })
+      .collect::<Vec<_>>();
+
+    // Stray pieces only touching other stray pieces are handed over once those have been
+    if moves.is_empty() { break };
+    for (piece, new) in moves {
+      for &i in piece {
+        labels[i] = new;
+      };
+    };
+  };
+}
+
+/// Every connected piece of each cluster, as lists of indices into `pixels`.
+fn find_pieces(pixels: &[[u32; 2]], labels: &[usize], index: &HashMap<[u32; 2], usize>) -> Vec<Vec<usize>> {
+  let mut visited = vec![false; pixels.len()];
+  let mut pieces = Vec::new();
+  let mut stack = Vec::new();
+  for start in 0..pixels.len() {
+    if visited[start] { continue };
+    visited[start] = true;
+    stack.push(start);
+
+    let mut piece = Vec::new();
+    while let Some(i) = stack.pop() {
+      piece.push(i);
+      for neighbour in neighbours(pixels[i]) {
+        if let Some(&j) = index.get(&neighbour) {
+          if !visited[j] && labels[j] == labels[i] {
+            visited[j] = true;
+            stack.push(j);
+          };
+        };
+      };
+    };
+
+    pieces.push(piece);
+  };
+
+  pieces
+}
+
+/// The four pixels sharing an edge with `pixel`, some of which may be off the map.
+#[inline]
+fn neighbours([x, y]: [u32; 2]) -> impl Iterator<Item = [u32; 2]> {
+  let left = x.checked_sub(1).map(|x| [x, y]);
+  let up = y.checked_sub(1).map(|y| [x, y]);
+  IntoIterator::into_iter([left, up, Some([x + 1, y]), Some([x, y + 1])]).flatten()
+}
+
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+error_enum!{
+  pub enum Error {
+    Io(io::Error),
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Remap(RemapError),
+    Custom(&'static str)
+  }
+}
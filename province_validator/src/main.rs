@@ -0,0 +1,108 @@
+#[macro_use] extern crate util_macros;
+extern crate bitmap;
+extern crate image;
+extern crate parse;
+
+use bitmap::{BitmapError, Connectivity, ValidateOptions};
+use parse::{CsvError, Def, Format};
+
+use std::path::Path;
+use std::{io, fs};
+
+fn main() {
+  match run() {
+    Ok(true) => (),
+    Ok(false) => std::process::exit(1),
+    Err(Error::Csv(err)) => {
+      eprintln!("error: unable to parse definition.csv: {}", err);
+      std::process::exit(2);
+    },
+    Err(Error::Bitmap(err)) => {
+      eprintln!("error: unable to read image: {}", err);
+      std::process::exit(2);
+    },
+    Err(err) => {
+      eprintln!("error: {:?}", err);
+      std::process::exit(2);
+    }
+  };
+}
+
+/// Returns false if any errors were found. Warnings alone do not fail validation.
+fn run() -> Result<bool, Error> {
+  let options = read_options()?;
+  let format = match arg_value("--format") {
+    Some(format) => format.parse::<Format>().map_err(|_| "invalid value for --format")?,
+    None => Format::Text
+  };
+
+  let defs = read_defs("definition.csv")?;
+  eprintln!("definitions read from definition.csv ({} provinces)", defs.len());
+
+  let img = bitmap::read_image("provinces.bmp")?;
+  eprintln!("image loaded from provinces.bmp");
+
+  let mut diagnostics = parse::validate_defs(&defs);
+  diagnostics.extend(bitmap::validate_map(&img, &defs, options));
+  let (errors, warnings) = parse::count_diagnostics(&diagnostics);
+
+  let report = parse::render_diagnostics(&diagnostics, format);
+  match arg_value("--output") {
+    Some(output) => {
+      fs::write(&output, report)?;
+      eprintln!("report written to {}", output);
+    },
+    None => print!("{}", report)
+  };
+
+  eprintln!("validation finished, {} errors and {} warnings found", errors, warnings);
+  Ok(errors == 0)
+}
+
+fn read_options() -> Result<ValidateOptions, Error> {
+  let mut options = ValidateOptions::default();
+  if let Some(min) = arg_value("--min-pixels") {
+    options.min_pixels = min.parse().map_err(|_| "invalid value for --min-pixels")?;
+  };
+
+  if let Some(max) = arg_value("--max-pixels") {
+    options.max_pixels = Some(max.parse().map_err(|_| "invalid value for --max-pixels")?);
+  };
+
+  if arg("--eight") {
+    options.graph.connectivity = Connectivity::Eight;
+  };
+
+  if arg("--no-wrap") {
+    options.graph.wrap_x = false;
+  };
+
+  Ok(options)
+}
+
+#[inline]
+fn arg(find: &str) -> bool {
+  std::env::args().skip(1).any(|a| a == find)
+}
+
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+fn read_defs<P: AsRef<Path>>(path: P) -> Result<Vec<Def>, Error> {
+  let data = fs::read_to_string(path)?;
+  let data = parse::parse_csv(data)?;
+  Ok(data)
+}
+
+error_enum!{
+  enum Error {
+    Io(io::Error),
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Custom(&'static str)
+  }
+}
@@ -1,221 +1,629 @@
 #[macro_use] extern crate util_macros;
+extern crate bitmap;
 extern crate parse;
+extern crate script;
 
-use image::{DynamicImage, ImageFormat, RgbImage, Rgb};
-use image::codecs::bmp::BmpDecoder;
+use image::RgbImage;
 
-use rand::distributions::{Standard, Distribution};
-use rand::Rng;
+use bitmap::{BitmapError, Progress};
+use parse::{ColorAllocator, CsvError, Def, Kind, ProvinceMap};
+use script::RemapError;
 
-use parse::{Def, Kind};
-
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::thread::spawn;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{fs, io};
 
+const BLACK: [u8; 3] = [0, 0, 0];
+/// Marks a pixel of the canvas that is black in every map.
+const NO_SOURCE: u8 = u8::MAX;
+
 fn main() {
-  if let Err(err) = run() {
-    println!("error: {:?}", err);
+  match run() {
+    Err(Error::Bitmap(err)) => println!("error: unable to read or write image: {}", err),
+    Err(err) => println!("error: {:?}", err),
+    Ok(()) => ()
   };
 }
 
 fn run() -> Result<(), Error> {
-  let defs1 = spawn(|| read_defs("definition_1.csv"));
-  let defs2 = spawn(|| read_defs("definition_2.csv"));
+  let count = count_sources();
+  if count < 2 {
+    return Err("could not find definition_1.csv, provinces_1.bmp, definition_2.csv and provinces_2.bmp".into());
+  } else if count > NO_SOURCE as usize {
+    return Err("too many maps to weld at once".into());
+  };
+
+  println!("found {} maps to weld", count);
+
+  let defs = (1..=count)
+    .map(|n| spawn(move || read_defs(format!("definition_{}.csv", n))))
+    .collect::<Vec<_>>();
   println!("reading defs...");
 
-  let defs1 = defs1.join().unwrap()?;
-  let defs2 = defs2.join().unwrap()?;
+  let defs = defs.into_iter()
+    .map(|handle| handle.join().unwrap())
+    .collect::<Result<Vec<_>, _>>()?;
   println!("defs read");
 
-  let provs1 = spawn(|| read_bmp("provinces_1.bmp"));
-  let provs2 = spawn(|| read_bmp("provinces_2.bmp"));
+  let provs = (1..=count)
+    .map(|n| spawn(move || bitmap::read_image(format!("provinces_{}.bmp", n))))
+    .collect::<Vec<_>>();
   println!("reading images...");
-  
-  let provs1 = provs1.join().unwrap()?;
-  let provs2 = provs2.join().unwrap()?;
+
+  let provs = provs.into_iter()
+    .map(|handle| handle.join().unwrap())
+    .collect::<Result<Vec<_>, _>>()?;
   println!("images read");
 
-  println!("{:?} | {:?}", provs1.dimensions(), provs2.dimensions());
-  assert_eq!(provs1.width(), provs2.width());
+  for (source, img) in provs.iter().enumerate() {
+    println!("map {}: {:?}", source + 1, img.dimensions());
+  };
+
+  let canvas = Canvas::open(&provs)?;
+  println!("maps placed at {:?} on a {:?} canvas", canvas.offsets, canvas.size);
+
+  let seed = match arg_value("--seed") {
+    Some(seed) => seed.parse::<u64>().map_err(|_| "invalid value for --seed")?,
+    None => 0
+  };
+
+  let policy = Policy::open()?;
+  let conflicting = get_conflicting(&defs);
+  let Scan { sources, visible, overlaps, neighbours } = scan_canvas(&provs, &canvas, &policy, &conflicting);
+  println!("pixels scanned, {} provinces visible", visible.len());
+  if !overlaps.is_empty() {
+    write_overlaps(&overlaps, &defs)?;
+    let pixels = overlaps.values().sum::<u64>();
+    println!("maps overlap by {} pixels, overlapping provinces written to overlaps.csv", pixels);
+    if let Policy::Fail = policy {
+      return Err("maps overlap and the conflict policy is fail".into());
+    };
+  };
+
+  let allocator = ColorAllocator::new(seed);
+  let replacement = get_replacement_map(&defs, &conflicting, &visible, &neighbours, allocator)?;
+  println!("replacement colors calculated (seed {})", seed);
+
+  write_recolored(&replacement, &defs)?;
+  for source in 0..count {
+    let recolored = replacement.keys().filter(|&&(_, s)| s == source).count();
+    println!("map {}: {} provinces recolored", source + 1, recolored);
+  };
 
-  let mut rng = rand::thread_rng();
-  let all_colors = get_common(&provs1, &provs2);
-  let replacement = get_replacement_map(&defs1, &defs2, &all_colors, &mut rng);
-  println!("replacement colors calculated");
-  let common = Arc::new(CommonData { all_colors, replacement });
+  println!("recolored provinces written to recolored.csv");
+  let common = Arc::new(CommonData { visible, replacement, canvas });
 
   let defs_common = common.clone();
-  let defs_handle = spawn(move || make_new_defs(defs1, defs2, defs_common));
+  let defs_handle = spawn(move || make_new_defs(defs, defs_common));
 
   let provs_common = common.clone();
-  let provs_handle = spawn(move || make_new_provs(provs1, provs2, provs_common));
+  let provs_handle = spawn(move || make_new_provs(provs, sources, provs_common));
 
-  defs_handle.join().unwrap()?;
+  let provenance = defs_handle.join().unwrap()?;
   println!("new defs finished");
 
+  write_provenance(&provenance)?;
+  println!("province mapping written to weld_map.csv");
+
+  let mods = (0..count)
+    .filter_map(|source| Some((source, PathBuf::from(arg_value(&format!("--mod-{}", source + 1))?))))
+    .collect::<Vec<_>>();
+  if !mods.is_empty() {
+    merge_mods(&mods, &provenance)?;
+  };
+
   provs_handle.join().unwrap()?;
   println!("new provs finished");
 
   Ok(())
 }
 
-type Replacement = HashMap<([u8; 3], Which), [u8; 3]>;
+/// Counts the maps in the working directory, numbered from 1 with no gaps.
+fn count_sources() -> usize {
+  let mut count = 0;
+  while Path::new(&format!("definition_{}.csv", count + 1)).exists() &&
+    Path::new(&format!("provinces_{}.bmp", count + 1)).exists() {
+    count += 1;
+  };
 
-fn make_new_defs(defs1: Vec<Def>, defs2: Vec<Def>, common: Arc<CommonData>) -> Result<(), Error> {
-  let size = defs1.len() + defs2.len();
-  let mut new_defs: Vec<Def> = Vec::with_capacity(size);
-  new_defs.push(Def::initial());
+  count
+}
 
-  for (mut def, which) in iter_defs_marked(defs1, defs2) {
-    if def.kind == Kind::Unknown { continue };
-    if !common.all_colors.contains(&def.rgb) { continue };
+/// How to pick a pixel where more than one map has one.
+enum Policy {
+  /// Uses the pixel from the lowest numbered map.
+  PreferFirst,
+  /// Uses the pixel from the highest numbered map.
+  PreferLast,
+  /// Prefers the highest numbered map where the mask is not black, and the lowest elsewhere.
+  Mask(RgbImage),
+  /// Refuses to weld overlapping maps.
+  Fail
+}
+
+impl Policy {
+  fn open() -> Result<Policy, Error> {
+    match arg_value("--policy").as_deref() {
+      None | Some("first") | Some("prefer1") => Ok(Policy::PreferFirst),
+      Some("last") | Some("prefer2") => Ok(Policy::PreferLast),
+      Some("mask") => Ok(Policy::Mask(bitmap::read_image("mask.bmp")?)),
+      Some("fail") => Ok(Policy::Fail),
+      Some(_) => Err("invalid value for --policy".into())
+    }
+  }
 
-    replace_rgb(&mut def.rgb, which, &common.replacement);
-    new_defs.push(def);
+  /// Picks between the maps that have a province at a position on the canvas, given in
+  /// order as `(map, color)` pairs. Returns `None` where every map is black.
+  #[inline]
+  fn resolve(&self, found: &[(Source, [u8; 3])], x: u32, y: u32) -> Option<([u8; 3], Source)> {
+    let last = match self {
+      Policy::PreferLast => true,
+      Policy::Mask(mask) => pixel_at(mask, x, y) != BLACK,
+      Policy::PreferFirst | Policy::Fail => false
+    };
+
+    let pick = if last { found.last() } else { found.first() };
+    pick.map(|&(source, rgb)| (rgb, source))
+  }
+}
+
+/// Where each map goes on the output image, and how large the output image is.
+#[derive(Debug, Clone)]
+struct Canvas {
+  offsets: Vec<[u32; 2]>,
+  size: [u32; 2]
+}
+
+impl Canvas {
+  fn open(provs: &[RgbImage]) -> Result<Canvas, Error> {
+    let mut offsets = Vec::with_capacity(provs.len());
+    for source in 0..provs.len() {
+      let flag = format!("--offset-{}", source + 1);
+      // `--offset` on its own places the second map, from when only two could be welded
+      let offset = arg_value(&flag).or_else(|| if source == 1 { arg_value("--offset") } else { None });
+      offsets.push(match offset {
+        Some(offset) => parse_pair(&offset, ',').ok_or("invalid value for --offset")?,
+        None => [0, 0]
+      });
+    };
+
+    let size = match arg_value("--size") {
+      Some(size) => parse_pair(&size, 'x').ok_or("invalid value for --size")?,
+      None => Iterator::zip(provs.iter(), offsets.iter())
+        .fold([0, 0], |[w, h], (img, &[ox, oy])| {
+          [w.max(ox + img.width()), h.max(oy + img.height())]
+        })
+    };
+
+    if size[0] == 0 || size[1] == 0 {
+      return Err("output size must not be empty".into());
+    };
+
+    Ok(Canvas { offsets, size })
+  }
+
+  /// Returns the pixel of a map at a position on the canvas, black where the map does
+  /// not cover it.
+  #[inline]
+  fn pixel(&self, provs: &[RgbImage], source: Source, x: u32, y: u32) -> [u8; 3] {
+    let [ox, oy] = self.offsets[source];
+    if x >= ox && y >= oy { pixel_at(&provs[source], x - ox, y - oy) } else { BLACK }
+  }
+}
+
+#[inline]
+fn pixel_at(img: &RgbImage, x: u32, y: u32) -> [u8; 3] {
+  if x < img.width() && y < img.height() { img.get_pixel(x, y).0 } else { BLACK }
+}
+
+fn parse_pair(s: &str, separator: char) -> Option<[u32; 2]> {
+  let mut parts = s.splitn(2, separator);
+  let a = parts.next()?.trim().parse().ok()?;
+  let b = parts.next()?.trim().parse().ok()?;
+  Some([a, b])
+}
+
+#[inline]
+fn arg_value(find: &str) -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  args.position(|a| a == find)?;
+  args.next()
+}
+
+/// The index of an input map, counting from 0 for `definition_1.csv` and `provinces_1.bmp`.
+type Source = usize;
+
+type Replacement = HashMap<([u8; 3], Source), [u8; 3]>;
+
+/// The provinces each conflicting province touches on the welded map.
+type Neighbours = HashMap<([u8; 3], Source), BTreeSet<([u8; 3], Source)>>;
+
+/// Where a province from one of the input maps ended up in the welded map, with `new` left
+/// empty for provinces that were dropped.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Provenance {
+  source: Source,
+  old_id: usize,
+  old_rgb: [u8; 3],
+  new: Option<(usize, [u8; 3])>
+}
+
+/// The map, id and color a province had before welding.
+type Origin = (Source, usize, [u8; 3]);
+
+fn make_new_defs(defs: Vec<Vec<Def>>, common: Arc<CommonData>) -> Result<Vec<Provenance>, Error> {
+  let size = defs.iter().map(Vec::len).sum();
+  let mut new_defs: Vec<(Def, Option<Origin>)> = Vec::with_capacity(size);
+  let mut provenance = Vec::with_capacity(size);
+  new_defs.push((Def::initial(), None));
+
+  for (mut def, source) in iter_defs_marked(defs) {
+    let (old_id, old_rgb) = (def.id, def.rgb);
+    if def.is_initial() {
+      provenance.push(Provenance { source, old_id, old_rgb, new: Some((0, BLACK)) });
+      continue;
+    };
+
+    if def.kind == Kind::Unknown || !common.visible.contains(&(def.rgb, source)) {
+      provenance.push(Provenance { source, old_id, old_rgb, new: None });
+      continue;
+    };
+
+    replace_rgb(&mut def.rgb, source, &common.replacement);
+    new_defs.push((def, Some((source, old_id, old_rgb))));
   };
 
-  new_defs.sort();
+  new_defs.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-  for (i, def) in new_defs.iter_mut().enumerate() {
+  for (i, (def, old)) in new_defs.iter_mut().enumerate() {
     def.id = i;
+    if let Some((source, old_id, old_rgb)) = *old {
+      provenance.push(Provenance { source, old_id, old_rgb, new: Some((i, def.rgb)) });
+    };
   };
 
   let new_defs = new_defs.into_iter()
-    .map(|def| def.to_string())
+    .map(|(def, _)| def.to_string())
     .collect::<String>();
   fs::write("definition_new.csv", new_defs)?;
 
+  provenance.sort();
+  Ok(provenance)
+}
+
+/// Merges the states, strategic regions and supply areas of the mods that go with each map,
+/// moving their provinces over to the welded ids.
+fn merge_mods(mods: &[(Source, PathBuf)], provenance: &[Provenance]) -> Result<(), Error> {
+  let out = arg_value("--mod-out").unwrap_or_else(|| "mod_new".to_owned());
+  let sources = mods.iter()
+    .map(|(source, dir)| {
+      let mut map = ProvinceMap::new();
+      for entry in provenance.iter().filter(|entry| entry.source == *source) {
+        map.insert(entry.old_id, entry.new.map(|(id, _)| id));
+      };
+
+      (dir.clone(), map)
+    })
+    .collect::<Vec<_>>();
+
+  let reports = script::merge_mod_dirs(&sources, &out)?;
+  for ((source, dir), report) in Iterator::zip(mods.iter(), reports.iter()) {
+    let name = format!("map {} ({})", source + 1, dir.display());
+    for (old, new) in &report.states {
+      match new {
+        Some(new) => println!("{}: state {} renumbered to {}", name, old, new),
+        None => println!("{}: state {} dropped, none of its provinces are left", name, old)
+      };
+    };
+
+    for (old, new) in &report.strategic_regions {
+      println!("{}: strategic region {} renumbered to {}", name, old, new);
+    };
+
+    for (old, new) in &report.supply_areas {
+      println!("{}: supply area {} renumbered to {}", name, old, new);
+    };
+
+    for id in &report.unknown_provinces {
+      println!("{}: province {} dropped, it is not in definition_{}.csv", name, id, source + 1);
+    };
+  };
+
+  println!("states, strategic regions and supply areas merged into {}", out);
   Ok(())
 }
 
-fn make_new_provs(provs1: RgbImage, provs2: RgbImage, common: Arc<CommonData>) -> Result<(), Error> {
-  let (width, height) = provs1.dimensions();
-  let mut new_provs = RgbImage::new(width, height);
-  let iter = Iterator::zip(provs1.pixels(), provs2.pixels());
-  let iter = Iterator::zip(new_provs.pixels_mut(), iter);
-  for (Rgb(new_pixel), (&Rgb(pixel1), &Rgb(pixel2))) in iter {
-    *new_pixel = make_new_pixel(pixel1, pixel2, &common);
+/// Writes `weld_map.csv`, listing every input province next to the id and color it has in
+/// the welded map. Dropped provinces have an empty new id and color.
+fn write_provenance(provenance: &[Provenance]) -> Result<(), Error> {
+  let mut out = String::from("map;old_id;old_r;old_g;old_b;new_id;new_r;new_g;new_b\n");
+  for entry in provenance {
+    let [r, g, b] = entry.old_rgb;
+    let new = match entry.new {
+      Some((id, [r, g, b])) => format!("{};{};{};{}", id, r, g, b),
+      None => ";;;".to_owned()
+    };
+
+    out.push_str(&format!("{};{};{};{};{};{}\n", entry.source + 1, entry.old_id, r, g, b, new));
   };
 
-  new_provs.save_with_format("provinces_new.bmp", ImageFormat::Bmp)?;
-  
+  fs::write("weld_map.csv", out)?;
   Ok(())
 }
 
-fn make_new_pixel(pixel1: [u8; 3], pixel2: [u8; 3], common: &CommonData) -> [u8; 3] {
-  fn f(rgb: [u8; 3], which: Which, replacement: &Replacement) -> Option<[u8; 3]> {
-    if rgb == [0, 0, 0] { return None };
-    let rgb = replacement.get(&(rgb, which))
-      .cloned().unwrap_or(rgb);
-    Some(rgb)
-  }
+fn make_new_provs(provs: Vec<RgbImage>, sources: Vec<u8>, common: Arc<CommonData>) -> Result<(), Error> {
+  let [width, height] = common.canvas.size;
+  let mut data = vec![0; width as usize * height as usize * 3];
+  bitmap::par_rows(&mut data, width as usize * 3, |first_row, data| {
+    let sources = &sources[first_row * width as usize..];
+    for (i, (pixel, &source)) in Iterator::zip(data.chunks_mut(3), sources.iter()).enumerate() {
+      let x = (i % width as usize) as u32;
+      let y = (first_row + i / width as usize) as u32;
+      pixel.copy_from_slice(&make_new_pixel(&provs, source, x, y, &common));
+    };
+  });
 
-  None
-    .or_else(|| f(pixel1, Which::Map1, &common.replacement))
-    .or_else(|| f(pixel2, Which::Map2, &common.replacement))
-    .unwrap_or([0, 0, 0])
+  let new_provs = RgbImage::from_raw(width, height, data).unwrap();
+  bitmap::write_bmp("provinces_new.bmp", &new_provs)?;
+  
+  Ok(())
+}
+
+#[inline]
+fn make_new_pixel(provs: &[RgbImage], source: u8, x: u32, y: u32, common: &CommonData) -> [u8; 3] {
+  if source == NO_SOURCE { return BLACK };
+  let source = source as Source;
+  let mut rgb = common.canvas.pixel(provs, source, x, y);
+  replace_rgb(&mut rgb, source, &common.replacement);
+  rgb
 }
 
 struct CommonData {
-  all_colors: HashSet<[u8; 3]>,
-  replacement: Replacement
+  visible: HashSet<([u8; 3], Source)>,
+  replacement: Replacement,
+  canvas: Canvas
 }
 
-fn replace_rgb(rgb: &mut [u8; 3], which: Which, replacement: &Replacement) {
-  if let Some(&new) = replacement.get(&(*rgb, which)) {
+fn replace_rgb(rgb: &mut [u8; 3], source: Source, replacement: &Replacement) {
+  if let Some(&new) = replacement.get(&(*rgb, source)) {
     *rgb = new;
   };
 }
 
-fn read_bmp<P: AsRef<Path>>(path: P) -> Result<RgbImage, Error> {
-  let img_file = fs::File::open(path)?;
-  let img = BmpDecoder::new(img_file)?;
-  let img = DynamicImage::from_decoder(img)?;
-  Ok(img.into_rgb8())
-}
-
 fn read_defs<P: AsRef<Path>>(path: P) -> Result<Vec<Def>, Error> {
   let data = fs::read_to_string(path)?;
-  let data =  parse::parse_csv(data)
-    .ok_or("failed to parse definitions")?;
+  let data = parse::parse_csv(data)?;
   Ok(data)
 }
 
-fn get_conflicting<'d>(
-  defs1: &'d [Def],
-  defs2: &'d [Def]
-) -> HashSet<&'d [u8; 3]> {
+/// Finds every color that a map shares with a lower numbered one. The lowest numbered map
+/// using a color keeps it, and every other map using it has to be recolored.
+fn get_conflicting(defs: &[Vec<Def>]) -> HashSet<([u8; 3], Source)> {
+  let mut owners: HashMap<[u8; 3], Source> = HashMap::new();
   let mut colors = HashSet::new();
-  for def1 in defs1 {
-    for def2 in defs2 {
-      if def1.rgb == def2.rgb {
-        colors.insert(&def1.rgb);
+  for (source, defs) in defs.iter().enumerate() {
+    for def in defs {
+      if def.rgb == BLACK { continue };
+      match *owners.entry(def.rgb).or_insert(source) {
+        owner if owner != source => { colors.insert((def.rgb, source)); },
+        _ => ()
       };
     };
   };
-  colors.shrink_to_fit();
   colors
 }
 
-fn get_replacement<'d>(
-  defs1: &'d [Def],
-  defs2: &'d [Def],
-  all_colors: &'d HashSet<[u8; 3]>,
-  target: usize,
-  rng: &mut impl Rng
-) -> HashSet<[u8; 3]> {
-  let mut colors = iter_defs(defs1, defs2)
-    .filter(|&def| !all_colors.contains(&def.rgb))
-    .map(|def| def.rgb.clone())
-    .collect::<HashSet<_>>();
-  colors.remove(&[0, 0, 0]);
-  while colors.len() < target {
-    let color = rng.gen::<[u8; 3]>();
-    if !all_colors.contains(&color) {
-      colors.insert(color);
+/// Gives each conflicting color a new one from `allocator`, kept visibly distinct from the
+/// provinces around it on the welded map. The result depends only on the inputs and the
+/// allocator's seed.
+fn get_replacement_map(
+  defs: &[Vec<Def>],
+  conflicting: &HashSet<([u8; 3], Source)>,
+  visible: &HashSet<([u8; 3], Source)>,
+  neighbours: &Neighbours,
+  mut allocator: ColorAllocator
+) -> Result<Replacement, Error> {
+  for defs in defs {
+    allocator.reserve_defs(defs);
+  };
+
+  for &(color, _) in visible {
+    allocator.reserve(color);
+  };
+
+  // Allocate in a fixed order, since each color depends on those given out before it
+  let mut conflicting = conflicting.iter().copied().collect::<Vec<_>>();
+  conflicting.sort();
+
+  let mut replacement = Replacement::new();
+  for key in conflicting {
+    let neighbours = neighbours.get(&key).into_iter().flatten()
+      .map(|neighbour| *replacement.get(neighbour).unwrap_or(&neighbour.0))
+      .collect::<Vec<_>>();
+    let new = allocator.allocate_near(&neighbours)
+      .ok_or("ran out of colors to give conflicting provinces")?;
+    replacement.insert(key, new);
+  };
+
+  Ok(replacement)
+}
+
+/// Pixels where two maps both have a province, keyed by the lower numbered map and its
+/// color, then the higher numbered map and its color.
+type Overlaps = BTreeMap<(Source, [u8; 3], Source, [u8; 3]), u64>;
+
+/// What a scan over every pixel of the canvas finds.
+struct Scan {
+  /// For each pixel of the canvas, which map it is taken from, or `NO_SOURCE`.
+  sources: Vec<u8>,
+  /// Every color that ends up visible along with the map it comes from, so that provinces
+  /// cut off or covered up can be dropped.
+  visible: HashSet<([u8; 3], Source)>,
+  overlaps: Overlaps,
+  neighbours: Neighbours
+}
+
+/// Resolves every pixel of the canvas with `policy`, counts the pixels where more than one
+/// map has a province, and finds what each of the `conflicting` provinces touches on the
+/// welded map, in a single pass split by rows across threads. Pixels touch along their edges,
+/// and the map wraps horizontally like it does in game.
+fn scan_canvas(provs: &[RgbImage], canvas: &Canvas, policy: &Policy, conflicting: &HashSet<([u8; 3], Source)>) -> Scan {
+  let [width, height] = canvas.size;
+  let find = |found: &mut Vec<(Source, [u8; 3])>, x: u32, y: u32| {
+    found.clear();
+    found.extend((0..provs.len())
+      .map(|source| (source, canvas.pixel(provs, source, x, y)))
+      .filter(|&(_, rgb)| rgb != BLACK));
+  };
+
+  let mut sources = vec![NO_SOURCE; width as usize * height as usize];
+  let progress = Progress::new("scanning pixels", height as usize);
+  let results = bitmap::par_rows(&mut sources, width as usize, |first_row, sources| {
+    let mut visible = HashSet::new();
+    let mut overlaps: HashMap<_, u64> = HashMap::new();
+    let mut neighbours = Neighbours::new();
+    let mut found = Vec::with_capacity(provs.len());
+    let mut last = None;
+    // The row above the band belongs to another thread, so it is resolved again here
+    let mut above = match first_row.checked_sub(1) {
+      Some(y) => (0..width).map(|x| { find(&mut found, x, y as u32); policy.resolve(&found, x, y as u32) }).collect(),
+      None => vec![None; width as usize]
+    };
+
+    let mut current = Vec::with_capacity(width as usize);
+    for (row, sources) in sources.chunks_mut(width as usize).enumerate() {
+      let y = (first_row + row) as u32;
+      current.clear();
+      for (x, source) in sources.iter_mut().enumerate() {
+        let x = x as u32;
+        find(&mut found, x, y);
+        for (i, &(source1, rgb1)) in found.iter().enumerate() {
+          for &(source2, rgb2) in &found[i + 1..] {
+            *overlaps.entry((source1, rgb1, source2, rgb2)).or_insert(0) += 1;
+          };
+        };
+
+        let pixel = policy.resolve(&found, x, y);
+        if let Some(pixel) = pixel {
+          *source = pixel.1 as u8;
+          // Neighbouring pixels are usually the same province, so skip hashing those
+          if last != Some(pixel) {
+            visible.insert(pixel);
+            last = Some(pixel);
+          };
+        };
+
+        current.push(pixel);
+      };
+
+      for x in 0..width as usize {
+        let right = current[(x + 1) % width as usize];
+        add_neighbours(&mut neighbours, conflicting, current[x], right);
+        add_neighbours(&mut neighbours, conflicting, current[x], above[x]);
+      };
+
+      std::mem::swap(&mut above, &mut current);
+      progress.advance();
+    };
+
+    (visible, overlaps, neighbours)
+  });
+
+  let mut visible = HashSet::new();
+  let mut overlaps = Overlaps::new();
+  let mut neighbours = Neighbours::new();
+  for (part_visible, part_overlaps, part_neighbours) in results {
+    visible.extend(part_visible);
+    for (key, pixels) in part_overlaps {
+      *overlaps.entry(key).or_insert(0) += pixels;
+    };
+
+    for (key, touching) in part_neighbours {
+      neighbours.entry(key).or_default().extend(touching);
     };
   };
-  colors.shrink_to_fit();
-  colors
+
+  Scan { sources, visible, overlaps, neighbours }
 }
 
-fn get_replacement_map<'d>(
-  defs1: &'d [Def],
-  defs2: &'d [Def],
-  all_colors: &'d HashSet<[u8; 3]>,
-  rng: &mut impl Rng
-) -> Replacement {
-  let conflicting = get_conflicting(defs1, defs2);
-  let replacement = get_replacement(defs1, defs2, all_colors, conflicting.len(), rng);
-  assert!(replacement.len() >= conflicting.len());
-  let conflicting = conflicting.into_iter()
-    .map(|color| (color.clone(), rng.gen()));
-  let replacement = replacement.into_iter();
-  Iterator::zip(conflicting, replacement).collect()
-}
-
-fn get_common(prov1: &RgbImage, prov2: &RgbImage) -> HashSet<[u8; 3]> {
-  let size = std::cmp::max(prov1.len(), prov2.len());
-  let mut colors = HashSet::with_capacity(size);
-  colors.extend(prov1.pixels().map(|&Rgb(p)| p));
-  colors.extend(prov2.pixels().map(|&Rgb(p)| p));
-  colors.remove(&[0, 0, 0]);
-  colors.shrink_to_fit();
-  colors
+/// Records that two touching pixels belong to each other's neighbours, for whichever of them
+/// is a conflicting province.
+#[inline]
+fn add_neighbours(
+  neighbours: &mut Neighbours,
+  conflicting: &HashSet<([u8; 3], Source)>,
+  a: Option<([u8; 3], Source)>,
+  b: Option<([u8; 3], Source)>
+) {
+  let (a, b) = match (a, b) {
+    (Some(a), Some(b)) if a != b => (a, b),
+    _ => return
+  };
+
+  if conflicting.contains(&a) {
+    neighbours.entry(a).or_default().insert(b);
+  };
+
+  if conflicting.contains(&b) {
+    neighbours.entry(b).or_default().insert(a);
+  };
+}
+
+/// Looks up province ids by color, separately for each map.
+fn get_ids(defs: &[Vec<Def>]) -> Vec<HashMap<[u8; 3], usize>> {
+  defs.iter()
+    .map(|defs| defs.iter().map(|def| (def.rgb, def.id)).collect())
+    .collect()
+}
+
+/// Writes `overlaps.csv`, listing each pair of overlapping provinces and how many pixels
+/// they share. Colors with no definition are given an empty id.
+fn write_overlaps(overlaps: &Overlaps, defs: &[Vec<Def>]) -> Result<(), Error> {
+  let ids = get_ids(defs);
+  let id = |source: Source, rgb| ids[source].get(rgb).map_or(String::new(), ToString::to_string);
+
+  let mut out = String::from("map_1;id_1;r_1;g_1;b_1;map_2;id_2;r_2;g_2;b_2;pixels\n");
+  for ((source1, rgb1, source2, rgb2), pixels) in overlaps {
+    let [r1, g1, b1] = rgb1;
+    let [r2, g2, b2] = rgb2;
+    out.push_str(&format!(
+      "{};{};{};{};{};{};{};{};{};{};{}\n",
+      source1 + 1, id(*source1, rgb1), r1, g1, b1,
+      source2 + 1, id(*source2, rgb2), r2, g2, b2,
+      pixels
+    ));
+  };
+
+  fs::write("overlaps.csv", out)?;
+  Ok(())
 }
 
-fn iter_defs<'d>(defs1: &'d [Def], defs2: &'d [Def]) -> impl Iterator<Item = &'d Def> {
-  Iterator::chain(defs1.iter(), defs2.iter())
+/// Writes `recolored.csv`, listing each province that was given a new color, by map.
+fn write_recolored(replacement: &Replacement, defs: &[Vec<Def>]) -> Result<(), Error> {
+  let ids = get_ids(defs);
+  let recolored = replacement.iter()
+    .map(|(&(rgb, source), &new)| (source, ids[source].get(&rgb).cloned(), rgb, new))
+    .collect::<BTreeSet<_>>();
+
+  let mut out = String::from("map;id;r;g;b;new_r;new_g;new_b\n");
+  for (source, id, [r, g, b], [new_r, new_g, new_b]) in recolored {
+    let id = id.map_or(String::new(), |id| id.to_string());
+    out.push_str(&format!(
+      "{};{};{};{};{};{};{};{}\n",
+      source + 1, id, r, g, b, new_r, new_g, new_b
+    ));
+  };
+
+  fs::write("recolored.csv", out)?;
+  Ok(())
 }
 
-fn iter_defs_marked(defs1: Vec<Def>, defs2: Vec<Def>) -> impl Iterator<Item = (Def, Which)> {
-  Iterator::chain(
-    defs1.into_iter().map(|def| (def, Which::Map1)),
-    defs2.into_iter().map(|def| (def, Which::Map2))
-  )
+fn iter_defs_marked(defs: Vec<Vec<Def>>) -> impl Iterator<Item = (Def, Source)> {
+  defs.into_iter()
+    .enumerate()
+    .flat_map(|(source, defs)| defs.into_iter().map(move |def| (def, source)))
 }
 
 /*struct Colors<'def> {
@@ -265,22 +673,12 @@ impl<'def, 'colors> Replacement<'def, 'colors> {
   }
 }*/
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
-enum Which {
-  Map1,
-  Map2
-}
-
-impl Distribution<Which> for Standard {
-  fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Which {
-    if rng.gen() { Which::Map1 } else { Which::Map2 }
-  }
-}
-
 error_enum!{
   pub enum Error {
     Io(io::Error),
-    Image(image::ImageError),
+    Bitmap(BitmapError),
+    Csv(CsvError),
+    Remap(RemapError),
     Custom(&'static str)
   }
 }
@@ -0,0 +1,664 @@
+use crate::error::ScriptError;
+
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::fmt;
+
+/// A byte range into the source a node was parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize) -> Span {
+    Span { start, end }
+  }
+
+  /// The 1-based line and column of the start of this span.
+  pub fn location(&self, source: &str) -> (usize, usize) {
+    let start = self.start.min(source.len());
+    let before = &source[..start];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+  Eq,
+  EqEq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Exists
+}
+
+impl Operator {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Operator::Eq => "=",
+      Operator::EqEq => "==",
+      Operator::Ne => "!=",
+      Operator::Lt => "<",
+      Operator::Le => "<=",
+      Operator::Gt => ">",
+      Operator::Ge => ">=",
+      Operator::Exists => "?="
+    }
+  }
+}
+
+impl fmt::Display for Operator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// A parsed script file. Every token remembers the whitespace and comments in front of it,
+/// so writing a script back out reproduces its source exactly, apart from any edits.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Script {
+  pub body: Body
+}
+
+impl Script {
+  pub fn new() -> Script {
+    Script::default()
+  }
+
+  pub fn parse(source: impl AsRef<str>) -> Result<Script, ScriptError> {
+    crate::parser::parse(source.as_ref())
+  }
+}
+
+impl Deref for Script {
+  type Target = Body;
+
+  fn deref(&self) -> &Body {
+    &self.body
+  }
+}
+
+impl DerefMut for Script {
+  fn deref_mut(&mut self) -> &mut Body {
+    &mut self.body
+  }
+}
+
+impl fmt::Display for Script {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.body)
+  }
+}
+
+/// The contents of a script file or of a block: a sequence of fields and bare values,
+/// followed by whatever trivia comes before the end of the file or the closing brace.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Body {
+  pub items: Vec<Item>,
+  pub post: String
+}
+
+impl Body {
+  pub fn fields(&self) -> impl Iterator<Item = &Field> {
+    self.items.iter().filter_map(Item::as_field)
+  }
+
+  pub fn fields_mut(&mut self) -> impl Iterator<Item = &mut Field> {
+    self.items.iter_mut().filter_map(Item::as_field_mut)
+  }
+
+  /// The bare values in this body, such as the numbers in `provinces = { 1 2 3 }`.
+  pub fn values(&self) -> impl Iterator<Item = &Value> {
+    self.items.iter().filter_map(Item::as_value)
+  }
+
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+    self.items.iter_mut().filter_map(Item::as_value_mut)
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    self.fields().find(|field| field.key.is(key)).map(|field| &field.value)
+  }
+
+  pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+    self.fields_mut().find(|field| field.key.is(key)).map(|field| &mut field.value)
+  }
+
+  pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Value> {
+    self.fields().filter(move |field| field.key.is(key)).map(|field| &field.value)
+  }
+
+  pub fn get_all_mut<'a>(&'a mut self, key: &'a str) -> impl Iterator<Item = &'a mut Value> {
+    self.fields_mut().filter(move |field| field.key.is(key)).map(|field| &mut field.value)
+  }
+
+  pub fn get_scalar(&self, key: &str) -> Option<&Scalar> {
+    self.get(key).and_then(Value::as_scalar)
+  }
+
+  pub fn get_block(&self, key: &str) -> Option<&Block> {
+    self.get(key).and_then(Value::as_block)
+  }
+
+  pub fn get_block_mut(&mut self, key: &str) -> Option<&mut Block> {
+    self.get_mut(key).and_then(Value::as_block_mut)
+  }
+
+  /// Appends `key = value`, indented like the items before it.
+  pub fn push_field(&mut self, key: &str, value: impl Into<Value>) {
+    let mut field = Field::new(key, value);
+    field.key.pre = self.next_pre();
+    self.items.push(Item::Field(field));
+  }
+
+  /// Appends a bare value, indented like the items before it.
+  pub fn push_value(&mut self, value: impl Into<Value>) {
+    let mut value = value.into();
+    *value.pre_mut() = self.next_pre();
+    self.items.push(Item::Value(value));
+  }
+
+  /// Removes every field with the given key, returning how many were removed.
+  pub fn remove(&mut self, key: &str) -> usize {
+    let len = self.items.len();
+    self.items.retain(|item| !matches!(item, Item::Field(field) if field.key.is(key)));
+    len - self.items.len()
+  }
+
+  /// Keeps only the items for which `f` returns true. The line break and indentation in front
+  /// of a removed item move onto the next item kept, if it shares a line with it, so that
+  /// removing the first item of a line does not pull the rest onto the line before.
+  pub fn retain<F>(&mut self, mut f: F)
+  where F: FnMut(&mut Item) -> bool {
+    let mut carried = None;
+    self.items.retain_mut(|item| {
+      if !f(item) {
+        carried.get_or_insert_with(|| item.pre().to_owned());
+        return false;
+      };
+
+      if let Some(pre) = carried.take() {
+        if !item.pre().contains('\n') {
+          *item.pre_mut() = pre;
+        };
+      };
+
+      true
+    });
+  }
+
+  fn next_pre(&mut self) -> String {
+    match self.items.last() {
+      // Reuse the line break and indentation of the previous item, dropping any comments
+      Some(item) => match item.pre().rfind('\n') {
+        Some(i) => format!("{}{}", line_break(&item.pre()[..=i]), &item.pre()[i + 1..]),
+        None => " ".to_owned()
+      },
+      // An empty multi-line block: indent one level past the closing brace
+      None if self.post.contains('\n') => {
+        let i = self.post.rfind('\n').unwrap();
+        format!("{}{}\t", line_break(&self.post[..=i]), &self.post[i + 1..])
+      },
+      None => {
+        if self.post.is_empty() {
+          self.post = " ".to_owned();
+        };
+
+        " ".to_owned()
+      }
+    }
+  }
+}
+
+impl fmt::Display for Body {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for item in &self.items {
+      write!(f, "{}", item)?;
+    };
+
+    write!(f, "{}", self.post)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+  Field(Field),
+  Value(Value)
+}
+
+impl Item {
+  pub fn as_field(&self) -> Option<&Field> {
+    match self {
+      Item::Field(field) => Some(field),
+      Item::Value(_) => None
+    }
+  }
+
+  pub fn as_field_mut(&mut self) -> Option<&mut Field> {
+    match self {
+      Item::Field(field) => Some(field),
+      Item::Value(_) => None
+    }
+  }
+
+  pub fn as_value(&self) -> Option<&Value> {
+    match self {
+      Item::Field(_) => None,
+      Item::Value(value) => Some(value)
+    }
+  }
+
+  pub fn as_value_mut(&mut self) -> Option<&mut Value> {
+    match self {
+      Item::Field(_) => None,
+      Item::Value(value) => Some(value)
+    }
+  }
+
+  /// The trivia in front of this item.
+  pub fn pre(&self) -> &str {
+    match self {
+      Item::Field(field) => &field.key.pre,
+      Item::Value(value) => value.pre()
+    }
+  }
+
+  pub fn pre_mut(&mut self) -> &mut String {
+    match self {
+      Item::Field(field) => &mut field.key.pre,
+      Item::Value(value) => value.pre_mut()
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    match self {
+      Item::Field(field) => field.span(),
+      Item::Value(value) => value.span()
+    }
+  }
+}
+
+impl fmt::Display for Item {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Item::Field(field) => write!(f, "{}", field),
+      Item::Value(value) => write!(f, "{}", value)
+    }
+  }
+}
+
+/// A `key = value` pair, or any other comparison such as `key > value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+  pub key: Scalar,
+  pub operator_pre: String,
+  pub operator: Operator,
+  pub value: Value
+}
+
+impl Field {
+  pub fn new(key: &str, value: impl Into<Value>) -> Field {
+    let mut value = value.into();
+    *value.pre_mut() = " ".to_owned();
+    Field {
+      key: Scalar::new(key),
+      operator_pre: " ".to_owned(),
+      operator: Operator::Eq,
+      value
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    Span::new(self.key.span.start, self.value.span().end)
+  }
+}
+
+impl fmt::Display for Field {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}{}{}{}", self.key, self.operator_pre, self.operator, self.value)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Scalar(Scalar),
+  Block(Block)
+}
+
+impl Value {
+  pub fn as_scalar(&self) -> Option<&Scalar> {
+    match self {
+      Value::Scalar(scalar) => Some(scalar),
+      Value::Block(_) => None
+    }
+  }
+
+  pub fn as_scalar_mut(&mut self) -> Option<&mut Scalar> {
+    match self {
+      Value::Scalar(scalar) => Some(scalar),
+      Value::Block(_) => None
+    }
+  }
+
+  pub fn as_block(&self) -> Option<&Block> {
+    match self {
+      Value::Scalar(_) => None,
+      Value::Block(block) => Some(block)
+    }
+  }
+
+  pub fn as_block_mut(&mut self) -> Option<&mut Block> {
+    match self {
+      Value::Scalar(_) => None,
+      Value::Block(block) => Some(block)
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    self.as_scalar().map(Scalar::as_str)
+  }
+
+  pub fn pre(&self) -> &str {
+    match self {
+      Value::Scalar(scalar) => &scalar.pre,
+      Value::Block(block) => &block.pre
+    }
+  }
+
+  pub fn pre_mut(&mut self) -> &mut String {
+    match self {
+      Value::Scalar(scalar) => &mut scalar.pre,
+      Value::Block(block) => &mut block.pre
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    match self {
+      Value::Scalar(scalar) => scalar.span,
+      Value::Block(block) => block.span
+    }
+  }
+}
+
+impl From<Scalar> for Value {
+  fn from(scalar: Scalar) -> Value {
+    Value::Scalar(scalar)
+  }
+}
+
+impl From<Block> for Value {
+  fn from(block: Block) -> Value {
+    Value::Block(block)
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Scalar(scalar) => write!(f, "{}", scalar),
+      Value::Block(block) => write!(f, "{}", block)
+    }
+  }
+}
+
+/// A `{ ... }` block, holding either fields, a list of values or a mix of both.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+  pub pre: String,
+  pub body: Body,
+  pub span: Span
+}
+
+impl Block {
+  pub fn new() -> Block {
+    Block::default()
+  }
+}
+
+impl Deref for Block {
+  type Target = Body;
+
+  fn deref(&self) -> &Body {
+    &self.body
+  }
+}
+
+impl DerefMut for Block {
+  fn deref_mut(&mut self) -> &mut Body {
+    &mut self.body
+  }
+}
+
+impl fmt::Display for Block {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}{{{}}}", self.pre, self.body)
+  }
+}
+
+/// A single word, number, date or quoted string, kept exactly as it was written.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Scalar {
+  pub pre: String,
+  raw: String,
+  pub span: Span
+}
+
+impl Scalar {
+  /// Creates a scalar, quoting it only if it would not survive being written bare.
+  pub fn new(text: impl ToString) -> Scalar {
+    let text = text.to_string();
+    if needs_quotes(&text) {
+      Scalar::quoted(text)
+    } else {
+      Scalar::from_raw(text)
+    }
+  }
+
+  pub fn quoted(text: impl AsRef<str>) -> Scalar {
+    Scalar::from_raw(format!("\"{}\"", text.as_ref().replace('"', "\\\"")))
+  }
+
+  pub(crate) fn from_raw(raw: String) -> Scalar {
+    Scalar { pre: String::new(), raw, span: Span::default() }
+  }
+
+  /// The text of this scalar, without surrounding quotes.
+  pub fn as_str(&self) -> &str {
+    match self.is_quoted() {
+      true => &self.raw[1..self.raw.len() - 1],
+      false => &self.raw
+    }
+  }
+
+  /// The text of this scalar exactly as written, including any quotes.
+  pub fn raw(&self) -> &str {
+    &self.raw
+  }
+
+  pub fn is_quoted(&self) -> bool {
+    self.raw.len() >= 2 && self.raw.starts_with('"') && self.raw.ends_with('"')
+  }
+
+  #[inline]
+  pub fn is(&self, text: &str) -> bool {
+    self.as_str() == text
+  }
+
+  pub fn parse<F: FromStr>(&self) -> Option<F> {
+    self.as_str().parse::<F>().ok()
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self.as_str() {
+      "yes" => Some(true),
+      "no" => Some(false),
+      _ => None
+    }
+  }
+
+  pub fn as_date(&self) -> Option<Date> {
+    self.parse::<Date>()
+  }
+
+  /// Replaces the text of this scalar, keeping it quoted if it was quoted before.
+  pub fn set(&mut self, text: impl ToString) {
+    let pre = std::mem::take(&mut self.pre);
+    let span = self.span;
+    *self = match self.is_quoted() {
+      true => Scalar::quoted(text.to_string()),
+      false => Scalar::new(text)
+    };
+    self.pre = pre;
+    self.span = span;
+  }
+}
+
+impl fmt::Display for Scalar {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}{}", self.pre, self.raw)
+  }
+}
+
+/// The line break `text` ends with, so that new lines match the file they are added to.
+#[inline]
+fn line_break(text: &str) -> &'static str {
+  if text.ends_with("\r\n") { "\r\n" } else { "\n" }
+}
+
+fn needs_quotes(text: &str) -> bool {
+  text.is_empty() || text.chars().any(|ch| {
+    ch.is_whitespace() || matches!(ch, '{' | '}' | '=' | '<' | '>' | '#' | '"' | '!' | '?')
+  })
+}
+
+/// A `year.month.day` date, optionally with an hour, as used in history files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Date {
+  pub year: i32,
+  pub month: u8,
+  pub day: u8,
+  pub hour: Option<u8>
+}
+
+impl FromStr for Date {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Date, ()> {
+    let mut parts = s.split('.');
+    let year = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+    let month = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+    let day = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+    let hour = match parts.next() {
+      Some(hour) => Some(hour.parse().map_err(|_| ())?),
+      None => None
+    };
+
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+      return Err(());
+    };
+
+    Ok(Date { year, month, day, hour })
+  }
+}
+
+impl fmt::Display for Date {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.year, self.month, self.day)?;
+    if let Some(hour) = self.hour {
+      write!(f, ".{}", hour)?;
+    };
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STATE: &str = "\u{feff}# A state with most of what the game writes\r\n\
+    state={\r\n\
+    \tid=1 # trailing comment\r\n\
+    \tname=\"STATE_1\"\r\n\
+    \r\n\
+    \thistory={\r\n\
+    \t\towner = FRA\r\n\
+    \t\tvictory_points = { 11 5.5 }\r\n\
+    \t\tif = { limit = { has_dlc ?= \"La Résistance\" } add_core_of=FRA }\r\n\
+    \t\t1939.1.1.12 = {\r\n\
+    \t\t\towner=GER\r\n\
+    \t\t}\r\n\
+    \t}\r\n\
+    \tprovinces={\r\n\
+    \t\t11 12 13\r\n\
+    \t}\r\n\
+    \tmanpower >= 100\r\n\
+    }\r\n\
+    # end of file\r\n";
+
+  #[test]
+  fn round_trip() {
+    let script = Script::parse(STATE).unwrap();
+    assert_eq!(script.to_string(), STATE);
+  }
+
+  #[test]
+  fn round_trip_without_trailing_newline() {
+    let source = "a = { b c }  # comment";
+    assert_eq!(Script::parse(source).unwrap().to_string(), source);
+    assert_eq!(Script::parse("").unwrap().to_string(), "");
+  }
+
+  #[test]
+  fn scalars_and_dates() {
+    let script = Script::parse(STATE).unwrap();
+    let state = script.get_block("state").unwrap();
+    assert_eq!(state.get_scalar("name").map(Scalar::as_str), Some("STATE_1"));
+    assert!(state.get_scalar("name").unwrap().is_quoted());
+    assert_eq!(state.get_scalar("id").and_then(Scalar::parse::<usize>), Some(1));
+    assert_eq!(state.get_scalar("manpower").and_then(Scalar::parse::<u32>), Some(100));
+
+    let history = state.get_block("history").unwrap();
+    let dated = history.fields().find_map(|field| field.key.as_date()).unwrap();
+    assert_eq!(dated, Date { year: 1939, month: 1, day: 1, hour: Some(12) });
+    assert_eq!(dated.to_string(), "1939.1.1.12");
+    assert_eq!("1939.13.1".parse::<Date>(), Err(()));
+  }
+
+  #[test]
+  fn edits_keep_surrounding_formatting() {
+    let mut script = Script::parse(STATE).unwrap();
+    let state = script.get_block_mut("state").unwrap();
+    state.get_mut("name").and_then(Value::as_scalar_mut).unwrap().set("STATE_2");
+    state.get_block_mut("provinces").unwrap().push_value(Scalar::new(14));
+    state.push_field("state_category", Scalar::new("town"));
+
+    let expected = STATE
+      .replace("STATE_1", "STATE_2")
+      .replace("11 12 13", "11 12 13 14")
+      .replace("\tmanpower >= 100\r\n", "\tmanpower >= 100\r\n\tstate_category = town\r\n");
+    assert_eq!(script.to_string(), expected);
+  }
+
+  #[test]
+  fn quoting() {
+    assert_eq!(Scalar::new("FRA").raw(), "FRA");
+    assert_eq!(Scalar::new("two words").raw(), "\"two words\"");
+    assert_eq!(Scalar::new("").raw(), "\"\"");
+    assert_eq!(Scalar::quoted("say \"hi\"").raw(), "\"say \\\"hi\\\"\"");
+  }
+
+  #[test]
+  fn error_location() {
+    let err = Script::parse("a = {\r\n\tb = 1\r\n}\r\n}").unwrap_err();
+    assert_eq!((err.line, err.column), (4, 1));
+    assert_eq!(err.to_string(), "line 4, column 1: unmatched `}`");
+  }
+}
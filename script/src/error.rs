@@ -0,0 +1,50 @@
+use crate::ast::Span;
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+  pub span: Span,
+  pub line: usize,
+  pub column: usize,
+  pub kind: ScriptErrorKind
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+  UnexpectedChar(char),
+  UnterminatedString,
+  UnexpectedOperator,
+  UnexpectedClose,
+  UnclosedBlock,
+  MissingValue
+}
+
+impl ScriptError {
+  pub(crate) fn new(source: &str, span: Span, kind: ScriptErrorKind) -> ScriptError {
+    let (line, column) = span.location(source);
+    ScriptError { span, line, column, kind }
+  }
+}
+
+impl fmt::Display for ScriptError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)
+  }
+}
+
+impl fmt::Display for ScriptErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ScriptErrorKind::UnexpectedChar(ch) => write!(f, "unexpected character `{}`", ch),
+      ScriptErrorKind::UnterminatedString => write!(f, "unterminated string"),
+      ScriptErrorKind::UnexpectedOperator => write!(f, "operator without a key"),
+      ScriptErrorKind::UnexpectedClose => write!(f, "unmatched `}}`"),
+      ScriptErrorKind::UnclosedBlock => write!(f, "block is never closed"),
+      ScriptErrorKind::MissingValue => write!(f, "operator is not followed by a value")
+    }
+  }
+}
+
+impl Error for ScriptError {}
@@ -0,0 +1,212 @@
+use crate::ast::{Operator, Span};
+use crate::error::{ScriptError, ScriptErrorKind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+  Open,
+  Close,
+  Operator(Operator),
+  Bare,
+  Quoted
+}
+
+/// A token along with the whitespace and comments that come before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token<'s> {
+  pub pre: &'s str,
+  pub kind: TokenKind,
+  pub text: &'s str,
+  pub span: Span
+}
+
+pub(crate) struct Lexer<'s> {
+  source: &'s str,
+  pos: usize
+}
+
+impl<'s> Lexer<'s> {
+  pub fn new(source: &'s str) -> Lexer<'s> {
+    Lexer { source, pos: 0 }
+  }
+
+  /// Returns the next token, or `Ok(None)` once only trivia is left.
+  pub fn next_token(&mut self) -> Result<Option<Token<'s>>, ScriptError> {
+    let pre_start = self.pos;
+    self.skip_trivia();
+    let pre = &self.source[pre_start..self.pos];
+
+    let start = self.pos;
+    let ch = match self.peek() {
+      Some(ch) => ch,
+      None => {
+        // Leave the trivia to be picked up by `rest`
+        self.pos = pre_start;
+        return Ok(None);
+      }
+    };
+
+    let kind = match ch {
+      '{' => { self.bump(); TokenKind::Open },
+      '}' => { self.bump(); TokenKind::Close },
+      '=' | '<' | '>' | '!' | '?' if self.is_operator() => {
+        TokenKind::Operator(self.operator(start)?)
+      },
+      '"' => { self.quoted(start)?; TokenKind::Quoted },
+      _ => { self.bare(); TokenKind::Bare }
+    };
+
+    let span = Span::new(start, self.pos);
+    Ok(Some(Token { pre, kind, text: &self.source[start..self.pos], span }))
+  }
+
+  pub fn pos(&self) -> usize {
+    self.pos
+  }
+
+  /// The trivia remaining after the last token.
+  pub fn rest(&mut self) -> &'s str {
+    let rest = &self.source[self.pos..];
+    self.pos = self.source.len();
+    rest
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.source[self.pos..].chars().next()
+  }
+
+  fn peek_second(&self) -> Option<char> {
+    self.source[self.pos..].chars().nth(1)
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let ch = self.peek()?;
+    self.pos += ch.len_utf8();
+    Some(ch)
+  }
+
+  fn skip_trivia(&mut self) {
+    while let Some(ch) = self.peek() {
+      if ch.is_whitespace() || ch == '\u{feff}' {
+        self.bump();
+      } else if ch == '#' {
+        while let Some(ch) = self.peek() {
+          if ch == '\n' { break };
+          self.bump();
+        };
+      } else {
+        break;
+      };
+    };
+  }
+
+  fn is_operator(&self) -> bool {
+    match self.peek() {
+      Some('=') | Some('<') | Some('>') => true,
+      // `!` and `?` only start an operator when followed by `=`
+      Some('!') | Some('?') => self.peek_second() == Some('='),
+      _ => false
+    }
+  }
+
+  fn operator(&mut self, start: usize) -> Result<Operator, ScriptError> {
+    let first = self.bump().unwrap();
+    let eq = self.peek() == Some('=');
+    if eq { self.bump(); };
+    match (first, eq) {
+      ('=', false) => Ok(Operator::Eq),
+      ('=', true) => Ok(Operator::EqEq),
+      ('<', false) => Ok(Operator::Lt),
+      ('<', true) => Ok(Operator::Le),
+      ('>', false) => Ok(Operator::Gt),
+      ('>', true) => Ok(Operator::Ge),
+      ('!', true) => Ok(Operator::Ne),
+      ('?', true) => Ok(Operator::Exists),
+      _ => Err(self.error(start, ScriptErrorKind::UnexpectedChar(first)))
+    }
+  }
+
+  fn quoted(&mut self, start: usize) -> Result<(), ScriptError> {
+    self.bump();
+    loop {
+      match self.bump() {
+        Some('"') => return Ok(()),
+        Some('\\') => { self.bump(); },
+        Some(_) => (),
+        None => return Err(self.error(start, ScriptErrorKind::UnterminatedString))
+      };
+    };
+  }
+
+  fn bare(&mut self) {
+    while let Some(ch) = self.peek() {
+      if ch.is_whitespace() || is_special(ch) { break };
+      if (ch == '!' || ch == '?') && self.peek_second() == Some('=') { break };
+      self.bump();
+    };
+  }
+
+  fn error(&self, start: usize, kind: ScriptErrorKind) -> ScriptError {
+    ScriptError::new(self.source, Span::new(start, self.pos.max(start + 1)), kind)
+  }
+}
+
+#[inline]
+fn is_special(ch: char) -> bool {
+  matches!(ch, '{' | '}' | '=' | '<' | '>' | '#' | '"')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lex(source: &str) -> Result<Vec<Token<'_>>, ScriptError> {
+    let mut lexer = Lexer::new(source);
+    let mut out = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+      out.push(token);
+    };
+
+    Ok(out)
+  }
+
+  fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+    lex(source).unwrap().into_iter().map(|token| (token.kind, token.text)).collect()
+  }
+
+  #[test]
+  fn operators() {
+    assert_eq!(kinds("a?=b c!=d e>=1 f<2 g==h"), vec![
+      (TokenKind::Bare, "a"), (TokenKind::Operator(Operator::Exists), "?="), (TokenKind::Bare, "b"),
+      (TokenKind::Bare, "c"), (TokenKind::Operator(Operator::Ne), "!="), (TokenKind::Bare, "d"),
+      (TokenKind::Bare, "e"), (TokenKind::Operator(Operator::Ge), ">="), (TokenKind::Bare, "1"),
+      (TokenKind::Bare, "f"), (TokenKind::Operator(Operator::Lt), "<"), (TokenKind::Bare, "2"),
+      (TokenKind::Bare, "g"), (TokenKind::Operator(Operator::EqEq), "=="), (TokenKind::Bare, "h")
+    ]);
+  }
+
+  #[test]
+  fn lone_punctuation_stays_in_bare_words() {
+    assert_eq!(kinds("what? yes!"), vec![(TokenKind::Bare, "what?"), (TokenKind::Bare, "yes!")]);
+  }
+
+  #[test]
+  fn trivia_and_quoted_strings() {
+    let mut lexer = Lexer::new("# comment\r\nname = \"a \\\" b\" # tail\r\n");
+    let name = lexer.next_token().unwrap().unwrap();
+    assert_eq!(name.pre, "# comment\r\n");
+    assert_eq!(name.span, Span::new(11, 15));
+    lexer.next_token().unwrap();
+    let value = lexer.next_token().unwrap().unwrap();
+    assert_eq!((value.kind, value.text), (TokenKind::Quoted, "\"a \\\" b\""));
+    assert_eq!(lexer.next_token().unwrap(), None);
+    assert_eq!(lexer.rest(), " # tail\r\n");
+  }
+
+  #[test]
+  fn unterminated_string() {
+    let err = lex("a = {\n\tb = \"open\n}").unwrap_err();
+    assert_eq!(err.kind, ScriptErrorKind::UnterminatedString);
+    assert_eq!(err.span, Span::new(11, 18));
+    assert_eq!((err.line, err.column), (2, 6));
+  }
+}
@@ -0,0 +1,15 @@
+extern crate parse;
+
+mod ast;
+mod error;
+mod lexer;
+mod merge;
+mod parser;
+mod remap;
+mod state;
+
+pub use crate::ast::*;
+pub use crate::error::*;
+pub use crate::merge::*;
+pub use crate::remap::*;
+pub use crate::state::*;
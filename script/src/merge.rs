@@ -0,0 +1,263 @@
+use parse::ProvinceMap;
+
+use crate::ast::*;
+use crate::remap::RemapError;
+use crate::state::{StateError, load_states, remap_values};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs;
+
+/// The ids that were changed while merging one mod, by kind. States that were dropped
+/// because none of their provinces survived are mapped to `None`. Provinces the mod refers
+/// to that its province map does not list are dropped, and collected in `unknown_provinces`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MergeReport {
+  pub states: BTreeMap<usize, Option<usize>>,
+  pub strategic_regions: BTreeMap<usize, usize>,
+  pub supply_areas: BTreeMap<usize, usize>,
+  pub unknown_provinces: BTreeSet<usize>
+}
+
+/// Hands out ids for one kind of object across several mods. Each object keeps its id unless
+/// an earlier mod already used it, in which case it gets one past every id in any of the mods.
+struct IdAllocator {
+  taken: BTreeSet<usize>,
+  next: usize
+}
+
+impl IdAllocator {
+  fn new(ids: impl IntoIterator<Item = usize>) -> IdAllocator {
+    let next = ids.into_iter().max().map_or(1, |max| max + 1);
+    IdAllocator { taken: BTreeSet::new(), next }
+  }
+
+  fn claim(&mut self, id: usize) -> usize {
+    if self.taken.insert(id) {
+      id
+    } else {
+      let id = self.next;
+      self.next += 1;
+      self.taken.insert(id);
+      id
+    }
+  }
+}
+
+/// Merges the `history/states`, `map/strategicregions` and `map/supplyareas` of several mods
+/// into `out`. Each mod comes with the province map that takes its province ids to the
+/// merged ones, such as the one a weld produces. Every province of the mod must be listed
+/// in its map, since ids are not shared between mods; any that are not get dropped.
+///
+/// State, strategic region and supply area ids that collide with those of an earlier mod are
+/// renumbered, and supply areas are updated to match. Nothing is written until every file
+/// has been read. Returns what was renumbered in each mod, in the order they were given.
+pub fn merge_mod_dirs(sources: &[(PathBuf, ProvinceMap)], out: impl AsRef<Path>) -> Result<Vec<MergeReport>, RemapError> {
+  let out = out.as_ref();
+  let mut states = Vec::with_capacity(sources.len());
+  let mut regions = Vec::with_capacity(sources.len());
+  let mut areas = Vec::with_capacity(sources.len());
+  for (dir, _) in sources {
+    let states_dir = dir.join("history/states");
+    states.push(if states_dir.is_dir() { load_states(&states_dir)? } else { Vec::new() });
+    regions.push(load_scripts(&dir.join("map/strategicregions"))?);
+    areas.push(load_scripts(&dir.join("map/supplyareas"))?);
+  };
+
+  let mut state_ids = IdAllocator::new(states.iter().flatten().map(|(_, state)| state.id));
+  let mut region_ids = IdAllocator::new(regions.iter().flatten().flat_map(|(_, script)| ids(script, "strategic_region")));
+  let mut area_ids = IdAllocator::new(areas.iter().flatten().flat_map(|(_, script)| ids(script, "supply_area")));
+
+  let mut reports = Vec::with_capacity(sources.len());
+  let mut writes = Writes::default();
+  for (source, (_, map)) in sources.iter().enumerate() {
+    let mut report = MergeReport::default();
+    let mut unknown = BTreeSet::new();
+    let mut lookup = |id: usize| if map.contains(id) {
+      map.get(id)
+    } else {
+      unknown.insert(id);
+      None
+    };
+
+    for (path, mut state) in std::mem::take(&mut states[source]) {
+      let old = state.id;
+      let had_provinces = !state.provinces.is_empty();
+      state.remap_provinces(&mut lookup)
+        .map_err(|err| StateError::File(path.clone(), Box::new(err)))?;
+      if had_provinces && state.provinces.is_empty() {
+        report.states.insert(old, None);
+        continue;
+      };
+
+      state.id = state_ids.claim(old);
+      if state.id != old {
+        report.states.insert(old, Some(state.id));
+      };
+
+      writes.push(out.join("history/states"), &path, old, state.id, source, state.to_string());
+    };
+
+    for (path, mut script) in std::mem::take(&mut regions[source]) {
+      let (old, new) = renumber(&mut script, "strategic_region", &mut region_ids, &mut report.strategic_regions);
+      for region in script.get_all_mut("strategic_region").filter_map(Value::as_block_mut) {
+        if let Some(provinces) = region.get_block_mut("provinces") {
+          remap_values(provinces, &mut lookup);
+        };
+      };
+
+      writes.push(out.join("map/strategicregions"), &path, old, new, source, script.to_string());
+    };
+
+    for (path, mut script) in std::mem::take(&mut areas[source]) {
+      let (old, new) = renumber(&mut script, "supply_area", &mut area_ids, &mut report.supply_areas);
+      for area in script.get_all_mut("supply_area").filter_map(Value::as_block_mut) {
+        if let Some(area_states) = area.get_block_mut("states") {
+          remap_values(area_states, |id| report.states.get(&id).cloned().unwrap_or(Some(id)));
+        };
+      };
+
+      writes.push(out.join("map/supplyareas"), &path, old, new, source, script.to_string());
+    };
+
+    report.unknown_provinces = unknown;
+    reports.push(report);
+  };
+
+  writes.write()?;
+  Ok(reports)
+}
+
+/// Files waiting to be written, under names that are unique within their directory.
+#[derive(Default)]
+struct Writes {
+  files: Vec<(PathBuf, String)>,
+  names: HashSet<PathBuf>
+}
+
+impl Writes {
+  /// Queues a file for writing under `dir`. If its id changed, the id at the start of its
+  /// name is changed too, and if its name is already taken it is prefixed with the number
+  /// of the mod it came from.
+  fn push(&mut self, dir: PathBuf, path: &Path, old: usize, new: usize, source: usize, content: String) {
+    let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let name = match (old == new, digits) {
+      (true, _) => name,
+      (false, 0) => format!("{}-{}", new, name),
+      (false, digits) => format!("{}{}", new, &name[digits..])
+    };
+
+    let mut path = dir.join(&name);
+    if self.names.contains(&path) {
+      path = dir.join(format!("{}-{}", source + 1, name));
+    };
+
+    self.names.insert(path.clone());
+    self.files.push((path, content));
+  }
+
+  fn write(self) -> Result<(), RemapError> {
+    for (path, content) in self.files {
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| RemapError::Io(dir.to_owned(), err))?;
+      };
+
+      fs::write(&path, content).map_err(|err| RemapError::Io(path.clone(), err))?;
+    };
+
+    Ok(())
+  }
+}
+
+/// Gives each `key` block in a script an id from `allocator`, recording any that changed.
+/// Returns the old and new id of the first block, which the file is named after.
+fn renumber(
+  script: &mut Script,
+  key: &str,
+  allocator: &mut IdAllocator,
+  changed: &mut BTreeMap<usize, usize>
+) -> (usize, usize) {
+  let mut first = None;
+  for block in script.get_all_mut(key).filter_map(Value::as_block_mut) {
+    let id = match block.get_mut("id").and_then(Value::as_scalar_mut) {
+      Some(id) => id,
+      None => continue
+    };
+
+    let old = match id.parse::<usize>() {
+      Some(old) => old,
+      None => continue
+    };
+
+    let new = allocator.claim(old);
+    if new != old {
+      id.set(new);
+      changed.insert(old, new);
+    };
+
+    first.get_or_insert((old, new));
+  };
+
+  first.unwrap_or((0, 0))
+}
+
+fn ids<'a>(script: &'a Script, key: &'a str) -> impl Iterator<Item = usize> + 'a {
+  script.get_all(key)
+    .filter_map(Value::as_block)
+    .filter_map(|block| block.get_scalar("id"))
+    .filter_map(|id| id.parse::<usize>())
+}
+
+fn load_scripts(dir: &Path) -> Result<Vec<(PathBuf, Script)>, RemapError> {
+  if !dir.is_dir() { return Ok(Vec::new()) };
+  let entries = fs::read_dir(dir).map_err(|err| RemapError::Io(dir.to_owned(), err))?;
+  let mut scripts = Vec::new();
+  for entry in entries {
+    let path = entry.map_err(|err| RemapError::Io(dir.to_owned(), err))?.path();
+    if path.extension() != Some("txt".as_ref()) { continue };
+    let content = fs::read_to_string(&path).map_err(|err| RemapError::Io(path.clone(), err))?;
+    match Script::parse(&content) {
+      Ok(script) => scripts.push((path, script)),
+      Err(err) => return Err(RemapError::Script(path, err))
+    };
+  };
+
+  scripts.sort_by(|(a, _), (b, _)| a.cmp(b));
+  Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn provinces_missing_from_the_map_are_dropped() {
+    let dir = std::env::temp_dir().join(format!("script_merge_{}", std::process::id()));
+    let (first, second, out) = (dir.join("first"), dir.join("second"), dir.join("out"));
+    for source in [&first, &second] {
+      fs::create_dir_all(source.join("history/states")).unwrap();
+      fs::write(source.join("history/states/1-A.txt"), "state = {\n\tid = 1\n\tprovinces = { 1 2 3 }\n}\n").unwrap();
+    };
+
+    let mut first_map = ProvinceMap::new();
+    first_map.insert(1, Some(1));
+    first_map.insert(2, Some(2));
+    let mut second_map = ProvinceMap::new();
+    second_map.insert(1, Some(4));
+    second_map.insert(2, Some(5));
+    second_map.insert(3, Some(6));
+
+    let reports = merge_mod_dirs(&[(first, first_map), (second, second_map)], &out);
+    let merged = fs::read_to_string(out.join("history/states/1-A.txt"));
+    let renumbered = fs::read_to_string(out.join("history/states/2-A.txt"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let reports = reports.unwrap();
+    assert_eq!(reports[0].unknown_provinces, BTreeSet::from([3]));
+    assert!(reports[1].unknown_provinces.is_empty());
+    assert_eq!(reports[1].states, BTreeMap::from([(1, Some(2))]));
+    assert!(merged.unwrap().contains("provinces = { 1 2 }"));
+    assert!(renumbered.unwrap().contains("provinces = { 4 5 6 }"));
+  }
+}
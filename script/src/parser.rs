@@ -0,0 +1,150 @@
+use crate::ast::*;
+use crate::error::{ScriptError, ScriptErrorKind};
+use crate::lexer::{Lexer, Token, TokenKind};
+
+pub(crate) fn parse(source: &str) -> Result<Script, ScriptError> {
+  let mut parser = Parser { source, lexer: Lexer::new(source), peeked: None };
+  let body = parser.body(None)?;
+  Ok(Script { body })
+}
+
+struct Parser<'s> {
+  source: &'s str,
+  lexer: Lexer<'s>,
+  peeked: Option<Token<'s>>
+}
+
+impl<'s> Parser<'s> {
+  fn next(&mut self) -> Result<Option<Token<'s>>, ScriptError> {
+    match self.peeked.take() {
+      Some(token) => Ok(Some(token)),
+      None => self.lexer.next_token()
+    }
+  }
+
+  fn peek(&mut self) -> Result<Option<&Token<'s>>, ScriptError> {
+    if self.peeked.is_none() {
+      self.peeked = self.lexer.next_token()?;
+    };
+
+    Ok(self.peeked.as_ref())
+  }
+
+  /// Parses items until the closing brace of the block opened at `open`,
+  /// or until the end of the file when `open` is `None`.
+  fn body(&mut self, open: Option<Span>) -> Result<Body, ScriptError> {
+    let mut items = Vec::new();
+    loop {
+      let token = match self.next()? {
+        Some(token) => token,
+        None => match open {
+          Some(open) => return Err(self.error(open, ScriptErrorKind::UnclosedBlock)),
+          None => return Ok(Body { items, post: self.lexer.rest().to_owned() })
+        }
+      };
+
+      match token.kind {
+        TokenKind::Close => match open {
+          Some(_) => return Ok(Body { items, post: token.pre.to_owned() }),
+          None => return Err(self.error(token.span, ScriptErrorKind::UnexpectedClose))
+        },
+        TokenKind::Operator(_) => {
+          return Err(self.error(token.span, ScriptErrorKind::UnexpectedOperator));
+        },
+        TokenKind::Open => {
+          items.push(Item::Value(Value::Block(self.block(token)?)));
+        },
+        TokenKind::Bare | TokenKind::Quoted => {
+          let key = scalar(&token);
+          match self.peek()?.map(|token| token.kind.clone()) {
+            Some(TokenKind::Operator(operator)) => {
+              let op = self.next()?.unwrap();
+              let value = self.value(op.span)?;
+              items.push(Item::Field(Field {
+                key,
+                operator_pre: op.pre.to_owned(),
+                operator,
+                value
+              }));
+            },
+            _ => items.push(Item::Value(Value::Scalar(key)))
+          };
+        }
+      };
+    };
+  }
+
+  fn block(&mut self, open: Token<'s>) -> Result<Block, ScriptError> {
+    let body = self.body(Some(open.span))?;
+    // The closing brace was the last token consumed, and nothing has been peeked since
+    let end = self.lexer.pos();
+    Ok(Block { pre: open.pre.to_owned(), body, span: Span::new(open.span.start, end) })
+  }
+
+  fn value(&mut self, op: Span) -> Result<Value, ScriptError> {
+    match self.next()? {
+      Some(token) => match token.kind {
+        TokenKind::Open => Ok(Value::Block(self.block(token)?)),
+        TokenKind::Bare | TokenKind::Quoted => Ok(Value::Scalar(scalar(&token))),
+        _ => Err(self.error(op, ScriptErrorKind::MissingValue))
+      },
+      None => Err(self.error(op, ScriptErrorKind::MissingValue))
+    }
+  }
+
+  fn error(&self, span: Span, kind: ScriptErrorKind) -> ScriptError {
+    ScriptError::new(self.source, span, kind)
+  }
+}
+
+fn scalar(token: &Token) -> Scalar {
+  let mut scalar = Scalar::from_raw(token.text.to_owned());
+  scalar.pre = token.pre.to_owned();
+  scalar.span = token.span;
+  scalar
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error(source: &str) -> (ScriptErrorKind, Span, (usize, usize)) {
+    let err = parse(source).unwrap_err();
+    (err.kind, err.span, (err.line, err.column))
+  }
+
+  #[test]
+  fn fields_values_and_blocks() {
+    let script = parse("a = { b ?= c 1 2 } d").unwrap();
+    assert_eq!(script.body.items.len(), 2);
+    let field = script.body.items[0].as_field().unwrap();
+    assert!(field.key.is("a"));
+    let block = field.value.as_block().unwrap();
+    assert_eq!(block.span, Span::new(4, 18));
+    assert_eq!(block.fields().next().unwrap().operator, Operator::Exists);
+    let values = block.values().filter_map(Value::as_str).collect::<Vec<_>>();
+    assert_eq!(values, ["1", "2"]);
+    assert_eq!(script.body.items[1].as_value().and_then(Value::as_str), Some("d"));
+  }
+
+  #[test]
+  fn unexpected_close() {
+    assert_eq!(error("a = 1\n}"), (ScriptErrorKind::UnexpectedClose, Span::new(6, 7), (2, 1)));
+  }
+
+  #[test]
+  fn unclosed_block() {
+    assert_eq!(error("a = {\n\tb = 1\n"), (ScriptErrorKind::UnclosedBlock, Span::new(4, 5), (1, 5)));
+  }
+
+  #[test]
+  fn operator_without_key() {
+    assert_eq!(error("a = 1\n= 2"), (ScriptErrorKind::UnexpectedOperator, Span::new(6, 7), (2, 1)));
+  }
+
+  #[test]
+  fn missing_value() {
+    assert_eq!(error("a = { b = }"), (ScriptErrorKind::MissingValue, Span::new(8, 9), (1, 9)));
+    assert_eq!(error("a >="), (ScriptErrorKind::MissingValue, Span::new(2, 4), (1, 3)));
+  }
+}
@@ -0,0 +1,333 @@
+use parse::ProvinceMap;
+
+use crate::ast::*;
+use crate::error::ScriptError;
+use crate::state::{StateError, load_states, remap_values};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::{fmt, fs, io};
+
+/// Remaps the bare province ids in a list block, dropping removed provinces.
+/// Returns how many ids were changed or dropped.
+pub fn remap_list(block: &mut Block, map: &ProvinceMap) -> usize {
+  remap_values(block, |id| map.get(id))
+}
+
+/// Remaps the `provinces` of every `strategic_region` in a `map/strategicregions` file.
+pub fn remap_strategic_regions(script: &mut Script, map: &ProvinceMap) -> usize {
+  script.get_all_mut("strategic_region")
+    .filter_map(Value::as_block_mut)
+    .filter_map(|region| region.get_block_mut("provinces"))
+    .map(|provinces| {
+      let listed = listed_ids(provinces);
+      remap_values(provinces, scoped(map, &listed))
+    })
+    .sum()
+}
+
+/// Remaps ids inside a single state or strategic region listing `listed`. A province merged
+/// into one listed elsewhere is dropped instead, since a province can only belong to one
+/// state and one strategic region.
+fn scoped<'a>(map: &'a ProvinceMap, listed: &[usize]) -> impl Fn(usize) -> Option<usize> + 'a {
+  let owned = listed.iter()
+    .filter(|&&id| !map.is_merged(id))
+    .filter_map(|&id| map.get(id))
+    .collect::<HashSet<_>>();
+  move |id| match map.get(id) {
+    Some(new) if map.is_merged(id) && !owned.contains(&new) => None,
+    new => new
+  }
+}
+
+/// The bare province ids in a list block.
+fn listed_ids(block: &Block) -> Vec<usize> {
+  block.values()
+    .filter_map(Value::as_scalar)
+    .filter_map(|scalar| scalar.parse::<usize>())
+    .collect()
+}
+
+/// Remaps `map/airports.txt` or `map/rocketsites.txt`, which list provinces per state id.
+pub fn remap_state_sites(script: &mut Script, map: &ProvinceMap) -> usize {
+  script.fields_mut()
+    .filter_map(|field| field.value.as_block_mut())
+    .map(|provinces| remap_list(provinces, map))
+    .sum()
+}
+
+/// Applies a province map to every file in a mod directory that refers to provinces,
+/// writing only the files that changed to the same place under `out`. The mod itself is
+/// left alone, so applying the same map again starts over from the original ids rather
+/// than remapping them twice. Files the mod does not have are skipped.
+///
+/// Supply areas refer to states rather than provinces, so they are not touched.
+/// Returns each file that was written, along with how many references changed in it.
+pub fn remap_mod_dir(
+  dir: impl AsRef<Path>,
+  map: &ProvinceMap,
+  out: impl AsRef<Path>
+) -> Result<Vec<(PathBuf, usize)>, RemapError> {
+  let (dir, out) = (dir.as_ref(), out.as_ref());
+  let mut edits = Vec::new();
+
+  let states = dir.join("history/states");
+  if states.is_dir() {
+    for (path, mut state) in load_states(&states)? {
+      let changed = state.remap_provinces(scoped(map, &state.provinces))
+        .map_err(|err| StateError::File(path.clone(), Box::new(err)))?;
+      if changed > 0 {
+        edits.push((path, state.to_string(), changed));
+      };
+    };
+  };
+
+  let regions = dir.join("map/strategicregions");
+  if regions.is_dir() {
+    for path in script_files(&regions)? {
+      edit_script(&mut edits, path, |script| remap_strategic_regions(script, map))?;
+    };
+  };
+
+  for &(file, f) in &[
+    ("map/airports.txt", remap_state_sites as fn(&mut Script, &ProvinceMap) -> usize),
+    ("map/rocketsites.txt", remap_state_sites)
+  ] {
+    let path = dir.join(file);
+    if path.is_file() {
+      edit_script(&mut edits, path, |script| f(script, map))?;
+    };
+  };
+
+  for &(file, f) in &[
+    ("map/adjacencies.csv", parse::remap_adjacencies as fn(&str, &ProvinceMap) -> (String, usize)),
+    ("map/buildings.txt", parse::remap_buildings),
+    ("map/unitstacks.txt", parse::remap_unitstacks),
+    ("map/railways.txt", parse::remap_railways),
+    ("map/supply_nodes.txt", parse::remap_supply_nodes)
+  ] {
+    let path = dir.join(file);
+    if path.is_file() {
+      let content = read(&path)?;
+      let (content, changed) = f(&content, map);
+      if changed > 0 {
+        edits.push((path, content, changed));
+      };
+    };
+  };
+
+  // Nothing is written until every file has been read and remapped successfully
+  let mut touched = Vec::with_capacity(edits.len());
+  for (path, content, changed) in edits {
+    let path = out.join(path.strip_prefix(dir).unwrap_or(&path));
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).map_err(|err| RemapError::Io(parent.to_owned(), err))?;
+    };
+
+    fs::write(&path, content).map_err(|err| RemapError::Io(path.clone(), err))?;
+    touched.push((path, changed));
+  };
+
+  Ok(touched)
+}
+
+/// Adds each of `new` to every state and strategic region in a mod directory that contains
+/// province `id`, such as after splitting it. Provinces are added after those already listed.
+/// Returns each file that was written, along with how many provinces were added to it.
+pub fn add_split_provinces(dir: impl AsRef<Path>, id: usize, new: &[usize]) -> Result<Vec<(PathBuf, usize)>, RemapError> {
+  let dir = dir.as_ref();
+  let mut edits = Vec::new();
+
+  let states = dir.join("history/states");
+  if states.is_dir() {
+    for (path, mut state) in load_states(&states)? {
+      if !state.provinces.contains(&id) { continue };
+      let before = state.provinces.len();
+      for &province in new {
+        if !state.provinces.contains(&province) {
+          state.provinces.push(province);
+        };
+      };
+
+      let added = state.provinces.len() - before;
+      if added > 0 {
+        edits.push((path, state.to_string(), added));
+      };
+    };
+  };
+
+  let regions = dir.join("map/strategicregions");
+  if regions.is_dir() {
+    for path in script_files(&regions)? {
+      edit_script(&mut edits, path, |script| {
+        script.get_all_mut("strategic_region")
+          .filter_map(Value::as_block_mut)
+          .filter_map(|region| region.get_block_mut("provinces"))
+          .map(|provinces| extend_list(provinces, id, new))
+          .sum()
+      })?;
+    };
+  };
+
+  let mut touched = Vec::with_capacity(edits.len());
+  for (path, content, added) in edits {
+    fs::write(&path, content).map_err(|err| RemapError::Io(path.clone(), err))?;
+    touched.push((path, added));
+  };
+
+  Ok(touched)
+}
+
+/// Appends each of `new` not already in a list block, if the list contains `id`.
+fn extend_list(block: &mut Block, id: usize, new: &[usize]) -> usize {
+  let listed = listed_ids(block);
+  if !listed.contains(&id) { return 0 };
+
+  let mut added = 0;
+  for &province in new {
+    if !listed.contains(&province) {
+      block.push_value(Scalar::new(province));
+      added += 1;
+    };
+  };
+
+  added
+}
+
+fn edit_script<F>(edits: &mut Vec<(PathBuf, String, usize)>, path: PathBuf, f: F) -> Result<(), RemapError>
+where F: FnOnce(&mut Script) -> usize {
+  let content = read(&path)?;
+  let mut script = match Script::parse(&content) {
+    Ok(script) => script,
+    Err(err) => return Err(RemapError::Script(path, err))
+  };
+
+  let changed = f(&mut script);
+  if changed > 0 {
+    edits.push((path, script.to_string(), changed));
+  };
+
+  Ok(())
+}
+
+fn script_files(dir: &Path) -> Result<Vec<PathBuf>, RemapError> {
+  let entries = fs::read_dir(dir).map_err(|err| RemapError::Io(dir.to_owned(), err))?;
+  let mut files = Vec::new();
+  for entry in entries {
+    let path = entry.map_err(|err| RemapError::Io(dir.to_owned(), err))?.path();
+    if path.extension() == Some("txt".as_ref()) {
+      files.push(path);
+    };
+  };
+
+  files.sort();
+  Ok(files)
+}
+
+fn read(path: &Path) -> Result<String, RemapError> {
+  fs::read_to_string(path).map_err(|err| RemapError::Io(path.to_owned(), err))
+}
+
+#[derive(Debug)]
+pub enum RemapError {
+  Io(PathBuf, io::Error),
+  Script(PathBuf, ScriptError),
+  State(StateError)
+}
+
+impl From<StateError> for RemapError {
+  fn from(err: StateError) -> RemapError {
+    RemapError::State(err)
+  }
+}
+
+impl fmt::Display for RemapError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RemapError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+      RemapError::Script(path, err) => write!(f, "{}: {}", path.display(), err),
+      RemapError::State(err) => write!(f, "{}", err)
+    }
+  }
+}
+
+impl Error for RemapError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("script_{}_{}", name, std::process::id()))
+  }
+
+  #[test]
+  fn merged_provinces_stay_in_the_survivors_region() {
+    let mut map = ProvinceMap::new();
+    map.insert(3, Some(2));
+    map.insert_merged(4, 1);
+    map.insert_merged(5, 6);
+
+    let with_survivor = scoped(&map, &[1, 4]);
+    assert_eq!(with_survivor(4), Some(1));
+    assert_eq!(with_survivor(3), Some(2));
+    let without_survivor = scoped(&map, &[5, 7]);
+    assert_eq!(without_survivor(5), None);
+    assert_eq!(without_survivor(7), Some(7));
+  }
+
+  #[test]
+  fn strategic_regions() {
+    let mut map = ProvinceMap::new();
+    map.insert(2, None);
+    map.insert_merged(3, 1);
+    let mut script = Script::parse("strategic_region = {\n\tid = 1\n\tprovinces = {\n\t\t1 2 3\n\t}\n}\n").unwrap();
+    assert_eq!(remap_strategic_regions(&mut script, &map), 2);
+    assert_eq!(script.to_string(), "strategic_region = {\n\tid = 1\n\tprovinces = {\n\t\t1\n\t}\n}\n");
+  }
+
+  #[test]
+  fn split_provinces_are_added_where_the_original_is() {
+    let dir = temp_dir("split");
+    fs::create_dir_all(dir.join("history/states")).unwrap();
+    fs::create_dir_all(dir.join("map/strategicregions")).unwrap();
+    fs::write(dir.join("history/states/1-A.txt"), "state = {\n\tid = 1\n\tprovinces = { 1 2 }\n}\n").unwrap();
+    fs::write(dir.join("history/states/2-B.txt"), "state = {\n\tid = 2\n\tprovinces = { 3 }\n}\n").unwrap();
+    fs::write(dir.join("map/strategicregions/1-R.txt"), "strategic_region = {\n\tid = 1\n\tprovinces = { 1 2 3 }\n}\n").unwrap();
+
+    let touched = add_split_provinces(&dir, 2, &[1, 4, 5]);
+    let state = fs::read_to_string(dir.join("history/states/1-A.txt"));
+    let region = fs::read_to_string(dir.join("map/strategicregions/1-R.txt"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    let touched = touched.unwrap().into_iter().map(|(_, added)| added).collect::<Vec<_>>();
+    assert_eq!(touched, [2, 2]);
+    assert!(state.unwrap().contains("provinces = { 1 2 4 5 }"));
+    assert!(region.unwrap().contains("provinces = { 1 2 3 4 5 }"));
+  }
+
+  #[test]
+  fn mod_dir_is_remapped_into_out() {
+    let dir = temp_dir("remap");
+    let (source, out) = (dir.join("mod"), dir.join("out"));
+    fs::create_dir_all(source.join("map")).unwrap();
+    fs::write(source.join("map/unitstacks.txt"), "2;0;1.0;2.0;3.0;0.0;0.0\n").unwrap();
+    fs::write(source.join("map/supply_nodes.txt"), "1 1\n").unwrap();
+
+    let mut map = ProvinceMap::new();
+    map.insert(2, Some(1));
+    let first = remap_mod_dir(&source, &map, &out);
+    let second = remap_mod_dir(&source, &map, &out);
+    let original = fs::read_to_string(source.join("map/unitstacks.txt"));
+    let remapped = fs::read_to_string(out.join("map/unitstacks.txt"));
+    let untouched = out.join("map/supply_nodes.txt").exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(first.unwrap(), [(out.join("map/unitstacks.txt"), 1)]);
+    assert_eq!(second.unwrap(), [(out.join("map/unitstacks.txt"), 1)]);
+    assert_eq!(original.unwrap(), "2;0;1.0;2.0;3.0;0.0;0.0\n");
+    assert_eq!(remapped.unwrap(), "1;0;1.0;2.0;3.0;0.0;0.0\n");
+    assert!(!untouched);
+  }
+}
@@ -0,0 +1,820 @@
+use crate::ast::*;
+use crate::error::ScriptError;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+/// The key of a missing or malformed field, and where it was found if it was present.
+type FieldError = (&'static str, Option<Span>);
+
+const TEMPLATE: &str = "state = {\n\tid = 0\n\tname = \"\"\n\tmanpower = 0\n\tstate_category = wasteland\n\n\thistory = {\n\t}\n\n\tprovinces = {\n\t}\n}\n";
+
+/// A state from `history/states`.
+///
+/// The fields cover the parts of a state the tools work with; everything else in the file,
+/// such as resources or dated history entries, is kept as-is and written back on save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+  pub id: usize,
+  pub name: String,
+  pub manpower: u64,
+  pub category: String,
+  pub provinces: Vec<usize>,
+  pub history: History,
+  script: Script
+}
+
+/// The undated part of a state's `history` block.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct History {
+  pub owner: Option<String>,
+  pub cores: Vec<String>,
+  pub victory_points: Vec<VictoryPoint>,
+  pub buildings: Buildings
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VictoryPoint {
+  pub province: usize,
+  pub value: f64
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Buildings {
+  /// State-wide buildings such as `infrastructure` or `arms_factory`, with their levels.
+  pub state: Vec<(String, u32)>,
+  /// Buildings placed in a specific province, such as `naval_base`.
+  pub provinces: Vec<(usize, Vec<(String, u32)>)>
+}
+
+impl State {
+  pub fn new(id: usize, name: impl Into<String>) -> State {
+    let mut state = State::parse(TEMPLATE).unwrap();
+    state.id = id;
+    state.name = name.into();
+    state
+  }
+
+  pub fn parse(source: impl AsRef<str>) -> Result<State, StateError> {
+    let source = source.as_ref();
+    let script = Script::parse(source)?;
+    State::from_script(script).map_err(|(key, span)| match span {
+      Some(span) => {
+        let (line, column) = span.location(source);
+        StateError::InvalidField { key, line, column }
+      },
+      None => StateError::MissingField(key)
+    })
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<State, StateError> {
+    State::parse(fs::read_to_string(path)?)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+    fs::write(path, self.to_string())
+  }
+
+  fn from_script(script: Script) -> Result<State, FieldError> {
+    let block = script.get_block("state").ok_or(("state", None))?;
+    let history = match block.get_block("history") {
+      Some(history) => History::from_body(history)?,
+      None => History::default()
+    };
+
+    let provinces = match block.get_block("provinces") {
+      Some(provinces) => list(provinces, "provinces")?,
+      None => Vec::new()
+    };
+
+    Ok(State {
+      id: required(block, "id")?,
+      name: block.get_scalar("name").map_or(String::new(), |name| name.as_str().to_owned()),
+      manpower: optional(block, "manpower")?.unwrap_or(0),
+      category: optional(block, "state_category")?.unwrap_or_default(),
+      provinces,
+      history,
+      script
+    })
+  }
+
+  /// Writes the typed fields back into the underlying script. Values that have not changed
+  /// are left untouched, so they keep their original formatting.
+  pub fn to_script(&self) -> Script {
+    let mut script = self.script.clone();
+    let block = block_mut(&mut script, "state");
+    set(block, "id", &self.id);
+    // Fields the file left out are only added once they have something other than the
+    // value they were read as
+    if !self.name.is_empty() || block.get("name").is_some() {
+      set_quoted(block, "name", &self.name);
+    };
+
+    if self.manpower != 0 || block.get("manpower").is_some() {
+      set(block, "manpower", &self.manpower);
+    };
+
+    if !self.category.is_empty() || block.get("state_category").is_some() {
+      set(block, "state_category", &self.category);
+    };
+    if self.history != History::default() || block.get("history").is_some() {
+      self.history.write(block_mut(block, "history"));
+    };
+
+    if !self.provinces.is_empty() || block.get("provinces").is_some() {
+      set_list(block_mut(block, "provinces"), &self.provinces);
+    };
+
+    script
+  }
+
+  /// Renumbers every province this state refers to, including those in dated history
+  /// entries. Provinces for which `f` returns `None` are dropped.
+  /// Returns how many references were changed or dropped, or an error if the edited state
+  /// cannot be read back, in which case the state is left as it was.
+  pub fn remap_provinces<F>(&mut self, mut f: F) -> Result<usize, StateError>
+  where F: FnMut(usize) -> Option<usize> {
+    // Edit the script in place rather than the typed fields, so that the items that
+    // survive keep their formatting, then read the typed fields back out of it
+    let mut script = self.to_script();
+    let mut changed = 0;
+    if let Some(block) = script.get_block_mut("state") {
+      if let Some(provinces) = block.get_block_mut("provinces") {
+        changed += remap_values(provinces, &mut f);
+      };
+
+      if let Some(history) = block.get_block_mut("history") {
+        changed += remap_history(history, &mut f);
+        for field in history.fields_mut() {
+          if field.key.as_date().is_none() { continue };
+          if let Some(entry) = field.value.as_block_mut() {
+            changed += remap_history(entry, &mut f);
+          };
+        };
+      };
+    };
+
+    // Read back from the printed script, so that any error points into the edited file
+    *self = State::parse(script.to_string())?;
+    Ok(changed)
+  }
+}
+
+/// Remaps the bare province ids in a list block, dropping removed provinces. Ids mapped onto
+/// one that is already listed, such as when provinces are merged, are dropped as well.
+pub(crate) fn remap_values<F>(block: &mut Block, mut f: F) -> usize
+where F: FnMut(usize) -> Option<usize> {
+  let mut listed = block.values()
+    .filter_map(Value::as_scalar)
+    .filter_map(|scalar| scalar.parse::<usize>())
+    .filter(|&id| f(id) == Some(id))
+    .collect::<HashSet<_>>();
+  let mut changed = 0;
+  block.retain(|item| {
+    let scalar = match item.as_value_mut().and_then(Value::as_scalar_mut) {
+      Some(scalar) => scalar,
+      None => return true
+    };
+
+    match scalar.parse::<usize>().map(|old| (old, f(old))) {
+      Some((old, Some(new))) if new != old && !listed.insert(new) => { changed += 1; false },
+      _ => remap_scalar(scalar, &mut f, &mut changed)
+    }
+  });
+
+  changed
+}
+
+/// Remaps the victory points and province buildings directly inside a history block.
+fn remap_history<F>(body: &mut Body, mut f: F) -> usize
+where F: FnMut(usize) -> Option<usize> {
+  let mut changed = 0;
+  body.retain(|item| {
+    let field = match item.as_field_mut() {
+      Some(field) if field.key.is("victory_points") => field,
+      _ => return true
+    };
+
+    let province = field.value.as_block_mut()
+      .and_then(|block| block.values_mut().next())
+      .and_then(Value::as_scalar_mut);
+    match province {
+      Some(province) => remap_scalar(province, &mut f, &mut changed),
+      None => true
+    }
+  });
+
+  if let Some(buildings) = body.get_block_mut("buildings") {
+    buildings.retain(|item| match item.as_field_mut() {
+      Some(field) => remap_scalar(&mut field.key, &mut f, &mut changed),
+      None => true
+    });
+
+    changed += merge_buildings(buildings);
+  };
+
+  changed + merge_victory_points(body)
+}
+
+/// Adds together victory points left on the same province, such as when provinces are merged,
+/// keeping only the first of them. Returns how many were dropped.
+fn merge_victory_points(body: &mut Body) -> usize {
+  fn point(item: &Item) -> Option<(usize, f64)> {
+    let field = item.as_field().filter(|field| field.key.is("victory_points"))?;
+    let mut values = field.value.as_block()?.values().filter_map(Value::as_scalar);
+    Some((values.next()?.parse()?, values.next()?.parse()?))
+  }
+
+  let mut totals: HashMap<usize, (f64, usize)> = HashMap::new();
+  for (province, value) in body.items.iter().filter_map(point) {
+    let total = totals.entry(province).or_insert((0.0, 0));
+    total.0 += value;
+    total.1 += 1;
+  };
+
+  let mut seen = HashSet::new();
+  let mut dropped = 0;
+  body.retain(|item| {
+    let province = match point(item) {
+      Some((province, _)) => province,
+      None => return true
+    };
+
+    if !seen.insert(province) {
+      dropped += 1;
+      return false;
+    };
+
+    let (total, count) = totals[&province];
+    let value = item.as_field_mut()
+      .and_then(|field| field.value.as_block_mut())
+      .and_then(|block| block.values_mut().filter_map(Value::as_scalar_mut).nth(1));
+    if let (Some(value), true) = (value, count > 1) {
+      value.set(total);
+    };
+
+    true
+  });
+
+  dropped
+}
+
+/// Combines the buildings of provinces left listed twice into the first of them, keeping the
+/// highest level of each building. Returns how many were dropped.
+fn merge_buildings(body: &mut Body) -> usize {
+  let mut first = HashMap::new();
+  let mut duplicates = Vec::new();
+  for (i, item) in body.items.iter().enumerate() {
+    let province = item.as_field()
+      .filter(|field| field.value.as_block().is_some())
+      .and_then(|field| field.key.parse::<usize>());
+    if let Some(province) = province {
+      match first.get(&province) {
+        Some(&target) => duplicates.push((target, i)),
+        None => { first.insert(province, i); }
+      };
+    };
+  };
+
+  let level = |field: &Field| field.value.as_scalar().and_then(Scalar::parse::<u32>);
+  for &(target, i) in &duplicates {
+    let fields = match body.items[i].as_field().and_then(|field| field.value.as_block()) {
+      Some(block) => block.fields().cloned().collect::<Vec<_>>(),
+      None => continue
+    };
+
+    let target = match body.items[target].as_field_mut().and_then(|field| field.value.as_block_mut()) {
+      Some(target) => target,
+      None => continue
+    };
+
+    for field in fields {
+      let existing = target.fields_mut().find(|existing| existing.key.is(field.key.as_str()));
+      match existing {
+        Some(existing) => match (level(existing), level(&field), existing.value.as_scalar_mut()) {
+          (Some(old), Some(new), Some(scalar)) if new > old => scalar.set(new),
+          _ => ()
+        },
+        None => target.push_field(field.key.as_str(), field.value)
+      };
+    };
+  };
+
+  let dropped = duplicates.iter().map(|&(_, i)| i).collect::<HashSet<_>>();
+  let mut index = 0..;
+  body.retain(|_| !dropped.contains(&index.next().unwrap()));
+  dropped.len()
+}
+
+/// Remaps a scalar if it is a province id, returning false if the province was removed.
+fn remap_scalar<F>(scalar: &mut Scalar, mut f: F, changed: &mut usize) -> bool
+where F: FnMut(usize) -> Option<usize> {
+  let old = match scalar.parse::<usize>() {
+    Some(old) => old,
+    None => return true
+  };
+
+  match f(old) {
+    Some(new) if new == old => true,
+    Some(new) => { scalar.set(new); *changed += 1; true },
+    None => { *changed += 1; false }
+  }
+}
+
+impl fmt::Display for State {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.to_script())
+  }
+}
+
+impl History {
+  fn from_body(body: &Body) -> Result<History, FieldError> {
+    let mut victory_points = Vec::new();
+    for value in body.get_all("victory_points") {
+      let block = value.as_block().ok_or(("victory_points", Some(value.span())))?;
+      let mut values = block.values().filter_map(Value::as_scalar);
+      let span = block.span;
+      let province = values.next().and_then(Scalar::parse).ok_or(("victory_points", Some(span)))?;
+      let value = values.next().and_then(Scalar::parse).ok_or(("victory_points", Some(span)))?;
+      victory_points.push(VictoryPoint { province, value });
+    };
+
+    let buildings = match body.get_block("buildings") {
+      Some(buildings) => Buildings::from_body(buildings)?,
+      None => Buildings::default()
+    };
+
+    Ok(History {
+      owner: optional(body, "owner")?,
+      cores: body.get_all("add_core_of").filter_map(Value::as_str).map(str::to_owned).collect(),
+      victory_points,
+      buildings
+    })
+  }
+
+  fn write(&self, body: &mut Body) {
+    match &self.owner {
+      Some(owner) => set(body, "owner", owner),
+      None => { body.remove("owner"); }
+    };
+
+    set_repeated(body, "add_core_of", &self.cores);
+    self.write_provinces(body);
+    if self.buildings != Buildings::default() || body.get("buildings").is_some() {
+      self.buildings.write(block_mut(body, "buildings"));
+    };
+  }
+
+  /// Writes only the parts of the history that refer to provinces.
+  fn write_provinces(&self, body: &mut Body) {
+    let victory_points = self.victory_points.iter()
+      .map(|vp| vec![vp.province.to_string(), vp.value.to_string()])
+      .collect::<Vec<_>>();
+    set_repeated_blocks(body, "victory_points", &victory_points);
+    if let Some(buildings) = body.get_block_mut("buildings") {
+      self.buildings.write_provinces(buildings);
+    };
+  }
+}
+
+impl Buildings {
+  fn from_body(body: &Body) -> Result<Buildings, FieldError> {
+    let mut buildings = Buildings::default();
+    for field in body.fields() {
+      match (field.key.parse::<usize>(), &field.value) {
+        (Some(province), Value::Block(block)) => {
+          buildings.provinces.push((province, levels(block)?));
+        },
+        (None, Value::Scalar(level)) => {
+          let level = level.parse().ok_or(("buildings", Some(level.span)))?;
+          buildings.state.push((field.key.as_str().to_owned(), level));
+        },
+        (_, value) => return Err(("buildings", Some(value.span())))
+      };
+    };
+
+    Ok(buildings)
+  }
+
+  fn write(&self, body: &mut Body) {
+    let existing = body.fields()
+      .filter(|field| field.key.parse::<usize>().is_none())
+      .map(|field| field.key.as_str().to_owned())
+      .collect::<Vec<_>>();
+    for key in existing {
+      if !self.state.iter().any(|(building, _)| *building == key) {
+        body.remove(&key);
+      };
+    };
+
+    for (building, level) in &self.state {
+      set(body, building, level);
+    };
+
+    self.write_provinces(body);
+  }
+
+  /// Matches province blocks up by position, so renumbered provinces keep their formatting.
+  fn write_provinces(&self, body: &mut Body) {
+    let mut provinces = self.provinces.iter();
+    let mut i = 0;
+    while i < body.items.len() {
+      let field = match body.items[i].as_field_mut() {
+        Some(field) if field.key.parse::<usize>().is_some() => field,
+        _ => { i += 1; continue }
+      };
+
+      match provinces.next() {
+        Some((province, levels)) => {
+          if field.key.parse::<usize>() != Some(*province) {
+            field.key.set(province);
+          };
+
+          if let Some(block) = field.value.as_block_mut() {
+            write_levels(block, levels);
+          };
+
+          i += 1;
+        },
+        None => { body.items.remove(i); }
+      };
+    };
+
+    for (province, levels) in provinces {
+      let mut block = Block::new();
+      write_levels(&mut block, levels);
+      body.push_field(&province.to_string(), block);
+    };
+  }
+}
+
+fn levels(block: &Block) -> Result<Vec<(String, u32)>, FieldError> {
+  block.fields()
+    .map(|field| {
+      let level = field.value.as_scalar().and_then(Scalar::parse);
+      let level = level.ok_or(("buildings", Some(field.value.span())))?;
+      Ok((field.key.as_str().to_owned(), level))
+    })
+    .collect()
+}
+
+fn write_levels(body: &mut Body, levels: &[(String, u32)]) {
+  let existing = body.fields().map(|field| field.key.as_str().to_owned()).collect::<Vec<_>>();
+  for key in existing {
+    if !levels.iter().any(|(building, _)| *building == key) {
+      body.remove(&key);
+    };
+  };
+
+  for (building, level) in levels {
+    set(body, building, level);
+  };
+}
+
+fn required<T: FromStr>(body: &Body, key: &'static str) -> Result<T, FieldError> {
+  optional(body, key)?.ok_or((key, None))
+}
+
+fn optional<T: FromStr>(body: &Body, key: &'static str) -> Result<Option<T>, FieldError> {
+  match body.get(key) {
+    Some(Value::Scalar(scalar)) => scalar.parse().map(Some).ok_or((key, Some(scalar.span))),
+    Some(Value::Block(block)) => Err((key, Some(block.span))),
+    None => Ok(None)
+  }
+}
+
+fn list<T: FromStr>(block: &Block, key: &'static str) -> Result<Vec<T>, FieldError> {
+  block.values()
+    .map(|value| value.as_scalar().and_then(Scalar::parse).ok_or((key, Some(value.span()))))
+    .collect()
+}
+
+/// Gets the block with the given key, adding an empty one if there is none.
+fn block_mut<'b>(body: &'b mut Body, key: &str) -> &'b mut Block {
+  if body.get_block(key).is_none() {
+    body.remove(key);
+    body.push_field(key, Block::new());
+  };
+
+  body.get_block_mut(key).unwrap()
+}
+
+/// Sets `key = value`, leaving the existing value alone if it already parses to `value`.
+fn set<T>(body: &mut Body, key: &str, value: &T)
+where T: ToString + FromStr + PartialEq {
+  match body.get_mut(key).and_then(Value::as_scalar_mut) {
+    Some(scalar) if scalar.parse::<T>().as_ref() == Some(value) => (),
+    Some(scalar) => scalar.set(value.to_string()),
+    None => {
+      body.remove(key);
+      body.push_field(key, Scalar::new(value.to_string()));
+    }
+  };
+}
+
+fn set_quoted(body: &mut Body, key: &str, value: &str) {
+  match body.get_mut(key).and_then(Value::as_scalar_mut) {
+    Some(scalar) if scalar.is(value) => (),
+    Some(scalar) => scalar.set(value),
+    None => {
+      body.remove(key);
+      body.push_field(key, Scalar::quoted(value));
+    }
+  };
+}
+
+/// Makes the values in a list block match `values`, reusing existing items by position.
+fn set_list<T>(block: &mut Block, values: &[T])
+where T: ToString + FromStr + PartialEq {
+  let mut values = values.iter();
+  let mut i = 0;
+  while i < block.items.len() {
+    let scalar = match block.items[i].as_value_mut().and_then(Value::as_scalar_mut) {
+      Some(scalar) => scalar,
+      None => { i += 1; continue }
+    };
+
+    match values.next() {
+      Some(value) => {
+        if scalar.parse::<T>().as_ref() != Some(value) {
+          scalar.set(value.to_string());
+        };
+
+        i += 1;
+      },
+      None => { block.items.remove(i); }
+    };
+  };
+
+  for value in values {
+    block.push_value(Scalar::new(value.to_string()));
+  };
+}
+
+/// Makes the repeated `key = value` fields match `values`, reusing existing fields by position.
+fn set_repeated(body: &mut Body, key: &str, values: &[String]) {
+  let mut values = values.iter();
+  let mut i = 0;
+  while i < body.items.len() {
+    let scalar = match body.items[i].as_field_mut() {
+      Some(field) if field.key.is(key) => field.value.as_scalar_mut(),
+      _ => { i += 1; continue }
+    };
+
+    match (values.next(), scalar) {
+      (Some(value), Some(scalar)) => {
+        if !scalar.is(value) { scalar.set(value) };
+        i += 1;
+      },
+      _ => { body.items.remove(i); }
+    };
+  };
+
+  for value in values {
+    body.push_field(key, Scalar::new(value));
+  };
+}
+
+/// Like `set_repeated`, but for fields whose values are small lists such as `victory_points`.
+fn set_repeated_blocks(body: &mut Body, key: &str, values: &[Vec<String>]) {
+  let mut values = values.iter();
+  let mut i = 0;
+  while i < body.items.len() {
+    let block = match body.items[i].as_field_mut() {
+      Some(field) if field.key.is(key) => field.value.as_block_mut(),
+      _ => { i += 1; continue }
+    };
+
+    match (values.next(), block) {
+      (Some(value), Some(block)) => {
+        if !same_values(block, value) {
+          set_list(block, value);
+        };
+
+        i += 1;
+      },
+      _ => { body.items.remove(i); }
+    };
+  };
+
+  for value in values {
+    let mut block = Block::new();
+    set_list(&mut block, value);
+    body.push_field(key, block);
+  };
+}
+
+/// Compares numerically where possible, so that `5.0` and `5` count as the same value.
+fn same_values(block: &Block, values: &[String]) -> bool {
+  let existing = block.values().filter_map(Value::as_str).collect::<Vec<_>>();
+  existing.len() == values.len() && Iterator::zip(existing.iter(), values).all(|(a, b)| {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+      (Ok(a), Ok(b)) => a == b,
+      _ => a == b
+    }
+  })
+}
+
+/// Loads every state file in a directory, such as `history/states`.
+pub fn load_states(dir: impl AsRef<Path>) -> Result<Vec<(PathBuf, State)>, StateError> {
+  let mut states = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.extension() != Some("txt".as_ref()) { continue };
+    match State::load(&path) {
+      Ok(state) => states.push((path, state)),
+      Err(err) => return Err(StateError::File(path, Box::new(err)))
+    };
+  };
+
+  states.sort_by_key(|(_, state)| state.id);
+  Ok(states)
+}
+
+#[derive(Debug)]
+pub enum StateError {
+  Io(io::Error),
+  Script(ScriptError),
+  MissingField(&'static str),
+  InvalidField { key: &'static str, line: usize, column: usize },
+  File(PathBuf, Box<StateError>)
+}
+
+impl From<io::Error> for StateError {
+  fn from(err: io::Error) -> StateError {
+    StateError::Io(err)
+  }
+}
+
+impl From<ScriptError> for StateError {
+  fn from(err: ScriptError) -> StateError {
+    StateError::Script(err)
+  }
+}
+
+impl fmt::Display for StateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      StateError::Io(err) => write!(f, "{}", err),
+      StateError::Script(err) => write!(f, "{}", err),
+      StateError::MissingField(key) => write!(f, "missing `{}`", key),
+      StateError::InvalidField { key, line, column } => {
+        write!(f, "line {}, column {}: invalid `{}`", line, column, key)
+      },
+      StateError::File(path, err) => write!(f, "{}: {}", path.display(), err)
+    }
+  }
+}
+
+impl Error for StateError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STATE: &str = "state={\n\
+    \tid=1\n\
+    \tname=\"STATE_1\" # Paris\n\
+    \tmanpower = 4500000\n\
+    \tstate_category = megalopolis\n\
+    \n\
+    \thistory={\n\
+    \t\towner = FRA\n\
+    \t\tadd_core_of = FRA\n\
+    \t\tvictory_points = {\n\
+    \t\t\t11 20\n\
+    \t\t}\n\
+    \t\tvictory_points = { 12 1.5 }\n\
+    \t\tbuildings = {\n\
+    \t\t\tinfrastructure = 4\n\
+    \t\t\t11 = {\n\
+    \t\t\t\tnaval_base = 2\n\
+    \t\t\t}\n\
+    \t\t\t12 = { naval_base = 5 bunker = 1 }\n\
+    \t\t}\n\
+    \t\t1939.1.1 = {\n\
+    \t\t\tvictory_points = { 13 3 }\n\
+    \t\t\tbuildings = { 13 = { coastal_bunker = 2 } }\n\
+    \t\t}\n\
+    \t}\n\
+    \n\
+    \tprovinces={\n\
+    \t\t11 12 13 14\n\
+    \t}\n\
+    }\n";
+
+  fn remapped<F>(f: F) -> String
+  where F: FnMut(usize) -> Option<usize> {
+    let mut state = State::parse(STATE).unwrap();
+    state.remap_provinces(f).unwrap();
+    state.to_string()
+  }
+
+  #[test]
+  fn round_trip() {
+    let state = State::parse(STATE).unwrap();
+    assert_eq!(state.to_string(), STATE);
+    assert_eq!(state.provinces, [11, 12, 13, 14]);
+    assert_eq!(state.history.victory_points, [
+      VictoryPoint { province: 11, value: 20.0 },
+      VictoryPoint { province: 12, value: 1.5 }
+    ]);
+    assert_eq!(state.history.buildings.state, [("infrastructure".to_owned(), 4)]);
+  }
+
+  #[test]
+  fn remap_renumbers_every_reference() {
+    let remapped = remapped(|id| Some(id + 100));
+    let expected = STATE.replace("11", "111").replace("12", "112").replace("13", "113").replace("14", "114");
+    assert_eq!(remapped, expected);
+  }
+
+  #[test]
+  fn remap_dated_history() {
+    let remapped = remapped(|id| if id == 13 { Some(30) } else { Some(id) });
+    assert!(remapped.contains("\t\t\tvictory_points = { 30 3 }\n\t\t\tbuildings = { 30 = { coastal_bunker = 2 } }\n"));
+    assert!(remapped.contains("\t\t11 12 30 14\n"));
+  }
+
+  #[test]
+  fn remove_first_province() {
+    let remapped = remapped(|id| if id == 11 { None } else { Some(id) });
+    assert!(remapped.contains("\tprovinces={\n\t\t12 13 14\n\t}\n"));
+    assert!(remapped.contains("\t\tadd_core_of = FRA\n\t\tvictory_points = { 12 1.5 }\n"));
+    assert!(remapped.contains("\t\t\tinfrastructure = 4\n\t\t\t12 = { naval_base = 5 bunker = 1 }\n"));
+  }
+
+  #[test]
+  fn remove_last_province() {
+    let remapped = remapped(|id| if id == 14 { None } else { Some(id) });
+    assert_eq!(remapped, STATE.replace("11 12 13 14", "11 12 13"));
+  }
+
+  #[test]
+  fn merged_provinces_are_listed_once() {
+    let mut block = Script::parse("{ 1 2 3 2 }").unwrap().body.items.remove(0);
+    let block = block.as_value_mut().and_then(Value::as_block_mut).unwrap();
+    let changed = remap_values(block, |id| if id == 3 { Some(1) } else { Some(id) });
+    assert_eq!(block.to_string(), "{ 1 2 2 }");
+    assert_eq!(changed, 1);
+  }
+
+  #[test]
+  fn merged_victory_points_are_summed() {
+    let remapped = remapped(|id| if id == 12 { Some(11) } else { Some(id) });
+    assert!(remapped.contains("\t\tvictory_points = {\n\t\t\t11 21.5\n\t\t}\n\t\tbuildings"));
+    assert!(!remapped.contains("1.5 }"));
+  }
+
+  #[test]
+  fn merged_buildings_take_the_highest_level() {
+    let remapped = remapped(|id| if id == 12 { Some(11) } else { Some(id) });
+    assert!(remapped.contains("\t\t\t11 = {\n\t\t\t\tnaval_base = 5\n\t\t\t\tbunker = 1\n\t\t\t}\n\t\t}\n"));
+  }
+
+  #[test]
+  fn edited_fields_keep_formatting() {
+    let mut state = State::parse(STATE).unwrap();
+    state.provinces = vec![11, 12, 15];
+    state.history.cores.push("GER".to_owned());
+    state.history.victory_points[1].value = 2.0;
+    let expected = STATE
+      .replace("11 12 13 14", "11 12 15")
+      .replace("\t\t}\n\t}\n\n\tprovinces", "\t\t}\n\t\tadd_core_of = GER\n\t}\n\n\tprovinces")
+      .replace("{ 12 1.5 }", "{ 12 2 }");
+    assert_eq!(state.to_string(), expected);
+  }
+
+  #[test]
+  fn removed_fields_are_dropped() {
+    let mut state = State::parse(STATE).unwrap();
+    state.history.cores.clear();
+    state.history.owner = None;
+    let expected = STATE.replace("\t\towner = FRA\n\t\tadd_core_of = FRA\n", "");
+    assert_eq!(state.to_string(), expected);
+  }
+
+  #[test]
+  fn invalid_field() {
+    let err = State::parse("state = {\n\tid = one\n}").unwrap_err();
+    assert!(matches!(err, StateError::InvalidField { key: "id", line: 2, column: 7 }));
+    assert!(matches!(State::parse("state = {}"), Err(StateError::MissingField("id"))));
+  }
+
+  #[test]
+  fn load_states_sorts_by_id() {
+    let dir = std::env::temp_dir().join(format!("script_load_states_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("2-B.txt"), "state = { id = 2 }").unwrap();
+    fs::write(dir.join("10-A.txt"), "state = { id = 10 }").unwrap();
+    fs::write(dir.join("notes.md"), "not a state").unwrap();
+    let states = load_states(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let ids = states.unwrap().iter().map(|(_, state)| state.id).collect::<Vec<_>>();
+    assert_eq!(ids, [2, 10]);
+  }
+}